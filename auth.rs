@@ -1,12 +1,11 @@
 use crate::types::{DeviceRegisterResponse, LoginResponse};
+use crate::net::{api_url, http_client};
 use anyhow::{anyhow, Result};
-use reqwest::Client;
 
-const PUSHOVER_API_URL: &str = "https://api.pushover.net/1";
 const DEVICE_NAME: &str = "miniover_client";
 
 pub async fn login(email: &str, password: &str, twofa: Option<&str>) -> Result<LoginResponse> {
-    let client = Client::new();
+    let client = http_client();
     let mut form = vec![
         ("email", email),
         ("password", password),
@@ -17,7 +16,7 @@ pub async fn login(email: &str, password: &str, twofa: Option<&str>) -> Result<L
     }
     
     let res = client
-        .post(&format!("{}/users/login.json", PUSHOVER_API_URL))
+        .post(format!("{}/users/login.json", api_url()))
         .form(&form)
         .send()
         .await?;
@@ -37,7 +36,7 @@ pub async fn login(email: &str, password: &str, twofa: Option<&str>) -> Result<L
 }
 
 pub async fn register_device(secret: &str) -> Result<DeviceRegisterResponse> {
-    let client = Client::new();
+    let client = http_client();
     let form = [
         ("secret", secret),
         ("name", DEVICE_NAME),
@@ -45,7 +44,7 @@ pub async fn register_device(secret: &str) -> Result<DeviceRegisterResponse> {
     ];
 
     let res = client
-        .post(&format!("{}/devices.json", PUSHOVER_API_URL))
+        .post(format!("{}/devices.json", api_url()))
         .form(&form)
         .send()
         .await?;
@@ -0,0 +1,145 @@
+use crate::settings::Override;
+use crate::utils::DirOptions;
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: miniover [OPTIONS] [COMMAND]
+
+Commands:
+  (none)           Run the tray client
+  status           Show login state and pending server actions
+  config show [--effective]
+                   Print the settings from settings.toml, or with --effective
+                   after applying MINIOVER_* variables and --set
+  help             Show this message
+
+Commands for the running tray client:
+  logs             Open the log directory
+  logout           Log out of Pushover
+  pause [DURATION] Pause notifications for 30m, 1h, tomorrow or indefinitely
+  resume           Resume notifications
+  reconnect        Reconnect to Pushover now
+  ack RECEIPT      Acknowledge an emergency message
+
+Options:
+  --set KEY=VALUE  Override a setting, e.g. --set quiet_hours.min_priority=1
+                   (when running, for status and for config show --effective)
+  --config-dir DIR Keep settings.toml, rules.json and credentials in DIR
+                   (or set MINIOVER_CONFIG_DIR)
+  --data-dir DIR   Keep logs and state in DIR (or set MINIOVER_DATA_DIR)
+  --portable       Keep everything next to the executable, in config/ and data/
+                   (also on when a file named portable is there)";
+
+#[derive(Debug)]
+pub enum Command {
+    Run,
+    Status,
+    ShowConfig { effective: bool },
+    Help,
+    // Forwarded to the running instance over the control socket
+    Control(Value),
+}
+
+pub struct Args {
+    pub command: Command,
+    // From --set, applied on top of settings.toml and the environment
+    pub overrides: Vec<Override>,
+    pub dirs: DirOptions,
+}
+
+pub fn parse_args() -> Result<Args> {
+    let mut overrides = Vec::new();
+    let mut dirs = DirOptions::default();
+    let mut rest = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--set" {
+            let setting = args.next().ok_or_else(|| anyhow!("--set needs KEY=VALUE"))?;
+            overrides.push(Override::parse_arg(&setting)?);
+        } else if let Some(setting) = arg.strip_prefix("--set=") {
+            overrides.push(Override::parse_arg(setting)?);
+        } else if let Some(dir) = dir_arg(&arg, "--config-dir", &mut args)? {
+            dirs.config_dir = Some(dir);
+        } else if let Some(dir) = dir_arg(&arg, "--data-dir", &mut args)? {
+            dirs.data_dir = Some(dir);
+        } else if arg == "--portable" {
+            dirs.portable = true;
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    let command = parse_command(&rest.iter().map(String::as_str).collect::<Vec<_>>())?;
+    if !overrides.is_empty() && matches!(command, Command::Control(_)) {
+        return Err(anyhow!("--set has no effect on the running tray client, change settings.toml instead"));
+    }
+    Ok(Args { command, overrides, dirs })
+}
+
+// `--name DIR` or `--name=DIR`
+fn dir_arg(arg: &str, name: &str, args: &mut impl Iterator<Item = String>) -> Result<Option<PathBuf>> {
+    let dir = match arg.strip_prefix(name) {
+        Some("") => args.next().ok_or_else(|| anyhow!("{} needs a directory", name))?,
+        Some(value) => match value.strip_prefix('=') {
+            Some(dir) => dir.to_string(),
+            None => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+    match dir.is_empty() {
+        true => Err(anyhow!("{} needs a directory", name)),
+        false => Ok(Some(PathBuf::from(dir))),
+    }
+}
+
+fn parse_command(args: &[&str]) -> Result<Command> {
+    match args {
+        [] => Ok(Command::Run),
+        ["status"] => Ok(Command::Status),
+        ["config", "show"] => Ok(Command::ShowConfig { effective: false }),
+        ["config", "show", "--effective"] => Ok(Command::ShowConfig { effective: true }),
+        ["help"] | ["-h"] | ["--help"] => Ok(Command::Help),
+        ["logs"] => Ok(Command::Control(json!({ "command": "show_logs" }))),
+        ["logout"] => Ok(Command::Control(json!({ "command": "logout" }))),
+        ["pause"] => Ok(Command::Control(json!({ "command": "pause" }))),
+        ["pause", duration] => Ok(Command::Control(json!({ "command": "pause", "duration": duration }))),
+        ["resume"] => Ok(Command::Control(json!({ "command": "resume" }))),
+        ["reconnect"] => Ok(Command::Control(json!({ "command": "reconnect" }))),
+        ["ack", receipt] => Ok(Command::Control(json!({ "command": "ack", "receipt": receipt }))),
+        [other, ..] => Err(anyhow!("Unknown command or arguments: {}\n\n{}", other, USAGE)),
+    }
+}
+
+// Hand a command to the running tray client and report its answer
+pub fn run_control(request: &Value) -> Result<()> {
+    let reply = crate::control::send_request(request)?;
+
+    match reply["ok"].as_bool() {
+        Some(true) => Ok(()),
+        _ => Err(anyhow!("{}", reply["error"].as_str().unwrap_or("Request failed"))),
+    }
+}
+
+pub fn show_config(overrides: &[Override], effective: bool) -> Result<()> {
+    let paths = crate::utils::app_paths();
+    let config = crate::utils::load_config(&paths.state_dir)?;
+    crate::settings::show(&paths.config_dir, config.legacy_preferences.as_ref(), overrides, effective)
+}
+
+pub fn print_usage() {
+    println!("{}", USAGE);
+}
+
+// The release binary uses the GUI subsystem on Windows, so borrow the
+// console of the shell that launched us for CLI output
+#[cfg(windows)]
+pub fn attach_parent_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn attach_parent_console() {}
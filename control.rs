@@ -0,0 +1,459 @@
+use crate::pause::PauseDuration;
+use crate::types::{AppState, ConnectionStatus, Event, Message};
+use crate::utils::{unix_now, AppPaths};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+
+// One JSON object per line, e.g. {"command":"pause","duration":"1h"}
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    Status,
+    // "30m", "1h", "tomorrow" or "indefinitely" (the default)
+    Pause { duration: Option<String> },
+    Resume,
+    Logout,
+    Reconnect,
+    Ack { receipt: String },
+    ShowLogs,
+    // Newest first, all recent messages without a limit
+    History { limit: Option<usize> },
+    // Stream {"event":"message",...} lines until the client disconnects
+    Subscribe,
+}
+
+// What the control socket needs from the running client
+#[derive(Clone)]
+pub struct Control {
+    pub app_state: Arc<Mutex<AppState>>,
+    pub status_rx: watch::Receiver<ConnectionStatus>,
+    pub events: mpsc::Sender<Event>,
+    pub delivered: broadcast::Sender<Message>,
+}
+
+impl Control {
+    async fn handle(&self, request: Request) -> Result<Value> {
+        let event = match request {
+            Request::Status => return Ok(self.status().await),
+            Request::History { limit } => {
+                let state = self.app_state.lock().await;
+                let messages: Vec<&Message> = state.recent.iter().take(limit.unwrap_or(usize::MAX)).collect();
+                return Ok(json!({ "ok": true, "messages": messages }));
+            }
+            Request::Pause { duration: None } => Event::Pause(PauseDuration::Indefinitely),
+            Request::Pause { duration: Some(duration) } => PauseDuration::parse(&duration)
+                .map(Event::Pause)
+                .ok_or_else(|| anyhow!("Unknown pause duration '{}'", duration))?,
+            Request::Resume => Event::Resume,
+            Request::Logout => Event::Logout,
+            Request::Reconnect => Event::Reconnect,
+            Request::Ack { receipt } => Event::Acknowledge(receipt),
+            Request::ShowLogs => Event::ShowLogs,
+            // Needs the connection to itself, see handle_client
+            Request::Subscribe => return Err(anyhow!("subscribe can't be combined with other requests")),
+        };
+
+        // Carried out by the tray task like a menu click
+        self.events
+            .send(event)
+            .await
+            .map_err(|_| anyhow!("miniover is shutting down"))?;
+        Ok(json!({ "ok": true }))
+    }
+
+    async fn status(&self) -> Value {
+        let connection = self.status_rx.borrow().clone();
+        let state = self.app_state.lock().await;
+        let pause = state
+            .config
+            .state
+            .pause
+            .as_ref()
+            .filter(|pause| pause.is_active(unix_now()))
+            .map(|pause| json!({ "until": pause.until, "held": pause.held.len() }));
+
+        json!({
+            "ok": true,
+            "pid": std::process::id(),
+            "logged_in": state.config.credentials.secret.is_some() && state.config.credentials.device_id.is_some(),
+            "connection": connection,
+            "pause": pause,
+            "quiet_held": state.config.state.quiet_held.len(),
+            "recent": state.recent.len(),
+        })
+    }
+}
+
+// "miniover", or for an instance with its own state dir "miniover-" and a hash of it, so
+// separate instances don't take each other's socket
+fn instance_name(paths: &AppPaths) -> String {
+    if !paths.custom_state_dir {
+        return "miniover".to_string();
+    }
+
+    let hash = Sha256::digest(paths.state_dir.as_os_str().as_encoded_bytes());
+    let hex: String = hash[..6].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("miniover-{}", hex)
+}
+
+pub fn start(control: Control) {
+    tokio::spawn(async move {
+        if let Err(e) = platform::serve(control).await {
+            error!("Control socket unavailable: {:#}", e);
+        }
+    });
+}
+
+// Send one request to the running instance and return its reply; used by the CLI
+pub fn send_request(request: &Value) -> Result<Value> {
+    let mut stream = platform::connect().context("miniover is not running")?;
+
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut reply = String::new();
+    std::io::BufReader::new(stream).read_line(&mut reply)?;
+    if reply.is_empty() {
+        return Err(anyhow!("miniover closed the connection without replying"));
+    }
+    Ok(serde_json::from_str(&reply)?)
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::{handle_client, instance_name, Control};
+    use crate::utils::app_paths;
+    use anyhow::{anyhow, Result};
+    use log::{debug, info};
+    use std::fs;
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use tokio::net::UnixListener;
+
+    const SOCKET_FILENAME: &str = "miniover.sock";
+    // Holds the socket when there is no $XDG_RUNTIME_DIR
+    const PRIVATE_DIRNAME: &str = "control";
+
+    // In $XDG_RUNTIME_DIR when there is one, which only the user can enter, otherwise in a
+    // directory of our own kept the same way
+    fn socket_path() -> PathBuf {
+        match dirs::runtime_dir() {
+            Some(dir) => dir.join(format!("{}.sock", instance_name(app_paths()))),
+            None => app_paths().state_dir.join(PRIVATE_DIRNAME).join(SOCKET_FILENAME),
+        }
+    }
+
+    pub fn connect() -> Result<std::os::unix::net::UnixStream> {
+        Ok(std::os::unix::net::UnixStream::connect(socket_path())?)
+    }
+
+    pub async fn serve(control: Control) -> Result<()> {
+        let path = socket_path();
+        let listener = bind(&path)?;
+        info!("Control socket listening on {}", path.display());
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let control = control.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_client(stream, control).await {
+                    debug!("Control client went away: {}", e);
+                }
+            });
+        }
+    }
+
+    fn bind(path: &Path) -> Result<UnixListener> {
+        // The socket is reachable from the moment it exists, before its mode can be set, so
+        // the directory has to keep others out
+        if dirs::runtime_dir().is_none()
+            && let Some(dir) = path.parent()
+        {
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        }
+
+        if path.exists() {
+            // A leftover from a crash is replaced, a live socket belongs to another instance
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(anyhow!("{} is in use by another miniover", path.display()));
+            }
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        Ok(listener)
+    }
+}
+
+// A named pipe per user. Remote clients are rejected, the pipe only lets the user's own
+// account in (the default DACL would give Everyone read access), and clients check that it
+// wasn't created first by someone else under our name
+#[cfg(windows)]
+mod platform {
+    use super::{handle_client, instance_name, Control};
+    use crate::utils::app_paths;
+    use anyhow::{anyhow, Result};
+    use log::{debug, info};
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use std::ptr;
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+    use windows_sys::Win32::Foundation::{CloseHandle, LocalFree, ERROR_SUCCESS, HANDLE, PSID};
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, GetSecurityInfo, SDDL_REVISION_1,
+        SE_KERNEL_OBJECT,
+    };
+    use windows_sys::Win32::Security::{
+        EqualSid, GetTokenInformation, TokenUser, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        TOKEN_QUERY, TOKEN_USER,
+    };
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    fn pipe_name() -> String {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!(r"\\.\pipe\{}-{}", instance_name(app_paths()), user)
+    }
+
+    // TOKEN_USER of this process; the SID it points to lives in the same buffer
+    struct CurrentUser(Vec<u64>);
+
+    impl CurrentUser {
+        fn get() -> Result<Self> {
+            unsafe {
+                let mut token: HANDLE = 0;
+                if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                let mut len = 0;
+                GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len);
+                let mut buffer = vec![0u64; (len as usize).div_ceil(8)];
+                let ok = GetTokenInformation(token, TokenUser, buffer.as_mut_ptr().cast(), len, &mut len);
+                let error = io::Error::last_os_error();
+                CloseHandle(token);
+                if ok == 0 {
+                    return Err(error.into());
+                }
+                Ok(Self(buffer))
+            }
+        }
+
+        fn sid(&self) -> PSID {
+            unsafe { (*self.0.as_ptr().cast::<TOKEN_USER>()).User.Sid }
+        }
+
+        fn sid_string(&self) -> Result<String> {
+            unsafe {
+                let mut string = ptr::null_mut();
+                if ConvertSidToStringSidW(self.sid(), &mut string) == 0 {
+                    return Err(io::Error::last_os_error().into());
+                }
+                let len = (0..).take_while(|&i| *string.add(i) != 0).count();
+                let sid = String::from_utf16_lossy(std::slice::from_raw_parts(string, len));
+                LocalFree(string.cast());
+                Ok(sid)
+            }
+        }
+    }
+
+    // Security descriptor owned by the user that gives nobody else any access
+    struct OwnerOnly(PSECURITY_DESCRIPTOR);
+
+    // Only ever read after it's built
+    unsafe impl Send for OwnerOnly {}
+
+    impl OwnerOnly {
+        fn new(user: &CurrentUser) -> Result<Self> {
+            let sid = user.sid_string()?;
+            // Protected DACL, so nothing is inherited and only this one entry applies
+            let sddl: Vec<u16> = format!("O:{}D:P(A;;GA;;;{})", sid, sid).encode_utf16().chain([0]).collect();
+            let mut descriptor = ptr::null_mut();
+            let ok = unsafe {
+                ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_ptr(), SDDL_REVISION_1, &mut descriptor, ptr::null_mut())
+            };
+            if ok == 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(Self(descriptor))
+        }
+
+        fn create(&self, name: &str, first: bool) -> Result<NamedPipeServer> {
+            let mut attributes = SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: self.0,
+                bInheritHandle: 0,
+            };
+            let server = unsafe {
+                ServerOptions::new()
+                    .first_pipe_instance(first)
+                    .create_with_security_attributes_raw(name, (&mut attributes as *mut SECURITY_ATTRIBUTES).cast())?
+            };
+            Ok(server)
+        }
+    }
+
+    impl Drop for OwnerOnly {
+        fn drop(&mut self) {
+            unsafe {
+                LocalFree(self.0);
+            }
+        }
+    }
+
+    pub fn connect() -> Result<File> {
+        let name = pipe_name();
+        let pipe = OpenOptions::new().read(true).write(true).open(&name)?;
+
+        let user = CurrentUser::get()?;
+        let mut owner: PSID = ptr::null_mut();
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        let ours = unsafe {
+            let status = GetSecurityInfo(
+                pipe.as_raw_handle() as HANDLE,
+                SE_KERNEL_OBJECT,
+                OWNER_SECURITY_INFORMATION,
+                &mut owner,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut descriptor,
+            );
+            if status != ERROR_SUCCESS {
+                return Err(io::Error::from_raw_os_error(status as i32).into());
+            }
+            let ours = EqualSid(owner, user.sid()) != 0;
+            LocalFree(descriptor);
+            ours
+        };
+        if !ours {
+            return Err(anyhow!("{} was created by another user", name));
+        }
+        Ok(pipe)
+    }
+
+    pub async fn serve(control: Control) -> Result<()> {
+        let name = pipe_name();
+        let security = OwnerOnly::new(&CurrentUser::get()?)?;
+        // Fails if the name is taken, including by a pipe someone else created to catch our clients
+        let mut server = security.create(&name, true)?;
+        info!("Control pipe listening on {}", name);
+
+        loop {
+            server.connect().await?;
+            // Open the next instance before serving this one so clients never find no pipe
+            let client = std::mem::replace(&mut server, security.create(&name, false)?);
+            let control = control.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_client(client, control).await {
+                    debug!("Control client went away: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite>(stream: S, control: Control) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                send(&mut writer, &failure(anyhow!("Invalid request: {}", e))).await?;
+                continue;
+            }
+        };
+
+        debug!("Control request: {:?}", request);
+        if let Request::Subscribe = request {
+            return subscribe(&control, writer, lines).await;
+        }
+
+        let reply = control.handle(request).await.unwrap_or_else(failure);
+        send(&mut writer, &reply).await?;
+    }
+
+    Ok(())
+}
+
+async fn subscribe<S: AsyncRead + AsyncWrite>(
+    control: &Control,
+    mut writer: WriteHalf<S>,
+    mut lines: Lines<BufReader<ReadHalf<S>>>,
+) -> Result<()> {
+    let mut delivered = control.delivered.subscribe();
+    send(&mut writer, &json!({ "ok": true })).await?;
+
+    loop {
+        tokio::select! {
+            message = delivered.recv() => match message {
+                Ok(message) => send(&mut writer, &json!({ "event": "message", "message": message })).await?,
+                Err(RecvError::Lagged(skipped)) => send(&mut writer, &json!({ "event": "lagged", "skipped": skipped })).await?,
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            // Further requests are ignored, this only notices the client leaving
+            line = lines.next_line() => {
+                if line?.is_none() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn failure(error: anyhow::Error) -> Value {
+    json!({ "ok": false, "error": error.to_string() })
+}
+
+async fn send<W: AsyncWrite>(writer: &mut WriteHalf<W>, reply: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(reply)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn paths(state_dir: &str, custom_state_dir: bool) -> AppPaths {
+        AppPaths {
+            config_dir: PathBuf::from("/config"),
+            data_dir: PathBuf::from(state_dir),
+            state_dir: PathBuf::from(state_dir),
+            log_dir: PathBuf::from(state_dir).join("logs"),
+            custom_state_dir,
+        }
+    }
+
+    #[test]
+    fn names_instances_after_their_state_dir() {
+        assert_eq!(instance_name(&paths("/home/jo/.local/state/miniover", false)), "miniover");
+
+        let first = instance_name(&paths("/srv/miniover/work", true));
+        let second = instance_name(&paths("/srv/miniover/home", true));
+        assert_ne!(first, second);
+        assert_eq!(first, instance_name(&paths("/srv/miniover/work", true)));
+        for name in [first, second] {
+            let hash = name.strip_prefix("miniover-").unwrap();
+            assert!(hash.len() == 12 && hash.chars().all(|c| c.is_ascii_hexdigit()), "{}", name);
+        }
+    }
+}
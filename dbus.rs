@@ -0,0 +1,396 @@
+use crate::pause::PauseDuration;
+use crate::types::{AppState, ConnectionStatus, Event, Message};
+use crate::utils::unix_now;
+use anyhow::Result;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{SerializeDict, Type};
+
+const BUS_NAME: &str = "io.github.miniover";
+const OBJECT_PATH: &str = "/io/github/miniover";
+
+// What the D-Bus service needs from the running client
+pub struct DbusContext {
+    pub app_state: Arc<Mutex<AppState>>,
+    pub status_rx: watch::Receiver<ConnectionStatus>,
+    pub events: mpsc::Sender<Event>,
+    pub delivered: broadcast::Sender<Message>,
+}
+
+// Reply of GetStatus, an a{sv} dictionary
+#[derive(SerializeDict, Type)]
+#[zvariant(signature = "a{sv}", rename_all = "PascalCase")]
+struct Status {
+    connection_state: String,
+    transport: String,
+    logged_in: bool,
+    last_connected: Option<i64>,
+    last_error: Option<String>,
+    paused: bool,
+    // Unix time, missing while paused indefinitely
+    paused_until: Option<i64>,
+    unread_count: u32,
+}
+
+// One entry of GetRecentMessages: (id, app, title, message, priority, date, url)
+#[derive(Serialize, Type)]
+struct RecentMessage {
+    id: i64,
+    app: String,
+    title: String,
+    message: String,
+    priority: i32,
+    date: i64,
+    url: String,
+}
+
+impl From<&Message> for RecentMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            id: message.id,
+            app: message.app.clone(),
+            title: message.title.clone().unwrap_or_default(),
+            message: message.message.clone(),
+            priority: message.priority,
+            date: message.date,
+            url: message.url.clone().unwrap_or_default(),
+        }
+    }
+}
+
+struct Service {
+    app_state: Arc<Mutex<AppState>>,
+    status_rx: watch::Receiver<ConnectionStatus>,
+    events: mpsc::Sender<Event>,
+    // Messages received since the last MarkRead
+    unread: u32,
+}
+
+impl Service {
+    // Hand the request to the tray task like a menu click
+    async fn send(&self, event: Event) -> fdo::Result<()> {
+        self.events
+            .send(event)
+            .await
+            .map_err(|_| fdo::Error::Failed("miniover is shutting down".to_string()))
+    }
+}
+
+#[zbus::interface(name = "io.github.miniover")]
+impl Service {
+    // "30m", "1h", "tomorrow", or "indefinitely" / "" to pause until resumed
+    async fn pause(&self, duration: &str) -> fdo::Result<()> {
+        let duration = match duration {
+            "" => PauseDuration::Indefinitely,
+            text => PauseDuration::parse(text)
+                .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown pause duration '{}'", text)))?,
+        };
+        self.send(Event::Pause(duration)).await
+    }
+
+    async fn resume(&self) -> fdo::Result<()> {
+        self.send(Event::Resume).await
+    }
+
+    async fn reconnect(&self) -> fdo::Result<()> {
+        self.send(Event::Reconnect).await
+    }
+
+    async fn acknowledge(&self, receipt: &str) -> fdo::Result<()> {
+        self.send(Event::Acknowledge(receipt.to_string())).await
+    }
+
+    async fn mark_read(&mut self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<()> {
+        self.unread = 0;
+        self.unread_count_changed(&emitter).await?;
+        Ok(())
+    }
+
+    async fn get_status(&self) -> Status {
+        let connection = self.status_rx.borrow().clone();
+        let state = self.app_state.lock().await;
+        let pause = state.config.state.pause.as_ref().filter(|pause| pause.is_active(unix_now()));
+
+        Status {
+            connection_state: state_name(&connection),
+            transport: serde_name(&connection.transport),
+            logged_in: state.config.credentials.secret.is_some() && state.config.credentials.device_id.is_some(),
+            last_connected: connection.last_connected,
+            last_error: connection.last_error,
+            paused: pause.is_some(),
+            paused_until: pause.and_then(|pause| pause.until),
+            unread_count: self.unread,
+        }
+    }
+
+    // Newest first
+    async fn get_recent_messages(&self) -> Vec<RecentMessage> {
+        self.app_state.lock().await.recent.iter().map(RecentMessage::from).collect()
+    }
+
+    // Same names the control socket uses, e.g. "connected" or "logged_out"
+    #[zbus(property)]
+    async fn connection_state(&self) -> String {
+        state_name(&self.status_rx.borrow())
+    }
+
+    #[zbus(property)]
+    async fn unread_count(&self) -> u32 {
+        self.unread
+    }
+
+    #[zbus(signal)]
+    async fn message_received(
+        emitter: &SignalEmitter<'_>,
+        id: i64,
+        app: &str,
+        title: &str,
+        message: &str,
+        priority: i32,
+        url: &str,
+    ) -> zbus::Result<()>;
+}
+
+fn state_name(status: &ConnectionStatus) -> String {
+    serde_name(&status.state)
+}
+
+// The snake_case name serde gives an enum variant
+fn serde_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+pub fn start(context: DbusContext) {
+    tokio::spawn(async move {
+        if let Err(e) = serve(context).await {
+            error!("D-Bus service unavailable: {:#}", e);
+        }
+    });
+}
+
+async fn serve(context: DbusContext) -> Result<()> {
+    serve_on(zbus::connection::Builder::session()?, context).await
+}
+
+// Own BUS_NAME on `bus` until the client shuts down
+async fn serve_on(bus: zbus::connection::Builder<'_>, context: DbusContext) -> Result<()> {
+    let DbusContext {
+        app_state,
+        mut status_rx,
+        events,
+        delivered,
+    } = context;
+    let mut delivered = delivered.subscribe();
+
+    let service = Service {
+        app_state,
+        status_rx: status_rx.clone(),
+        events,
+        unread: 0,
+    };
+    let connection = bus
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()
+        .await?;
+    let service = connection.object_server().interface::<_, Service>(OBJECT_PATH).await?;
+    info!("D-Bus service {} registered", BUS_NAME);
+
+    loop {
+        tokio::select! {
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                status_rx.borrow_and_update();
+                if let Err(e) = service.get().await.connection_state_changed(service.signal_emitter()).await {
+                    warn!("Failed to signal ConnectionState change: {}", e);
+                }
+            }
+            message = delivered.recv() => {
+                let message = match message {
+                    Ok(message) => message,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                let emitter = service.signal_emitter();
+                let message = RecentMessage::from(&message);
+                if let Err(e) = Service::message_received(
+                    emitter,
+                    message.id,
+                    &message.app,
+                    &message.title,
+                    &message.message,
+                    message.priority,
+                    &message.url,
+                )
+                .await
+                {
+                    warn!("Failed to emit MessageReceived: {}", e);
+                }
+
+                let mut iface = service.get_mut().await;
+                iface.unread = iface.unread.saturating_add(1);
+                if let Err(e) = iface.unread_count_changed(emitter).await {
+                    warn!("Failed to signal UnreadCount change: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Config, ConnectionState};
+    use crate::utils::find_program;
+    use futures_util::StreamExt;
+    use std::io::{BufRead, BufReader};
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+    use tokio::time;
+    use zbus::proxy::CacheProperties;
+
+    const WAIT: Duration = Duration::from_secs(10);
+
+    // A private session bus, stopped when dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start(daemon: &Path) -> Self {
+            let mut daemon = Command::new(daemon)
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon starts");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_the_session_bus() {
+        let Some(daemon) = find_program("dbus-daemon") else {
+            eprintln!("dbus-daemon isn't installed, skipping");
+            return;
+        };
+        let bus = Bus::start(&daemon);
+        let (status_tx, status_rx) = watch::channel(ConnectionStatus::default());
+        let (events_tx, mut events) = mpsc::channel(8);
+        let (delivered, _) = broadcast::channel(8);
+        let context = DbusContext {
+            app_state: Arc::new(Mutex::new(AppState::new(Config::default()))),
+            status_rx,
+            events: events_tx,
+            delivered: delivered.clone(),
+        };
+        let server = tokio::spawn(serve_on(zbus::connection::Builder::address(bus.address.as_str()).unwrap(), context));
+
+        let connection = zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy: zbus::Proxy = zbus::proxy::Builder::new(&connection)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .interface(BUS_NAME)
+            .unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+
+        // Wait for the service to own its name
+        time::timeout(WAIT, async {
+            while proxy.get_property::<u32>("UnreadCount").await.is_err() {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("service registered");
+
+        // Methods turn into events for the tray task
+        let calls: [(&str, &str, &str); 4] = [
+            ("Pause", "1h", "Pause(OneHour)"),
+            ("Pause", "", "Pause(Indefinitely)"),
+            ("Acknowledge", "r1234", "Acknowledge(\"r1234\")"),
+            ("Resume", "", "Resume"),
+        ];
+        for (method, argument, expected) in calls {
+            match method {
+                "Resume" => proxy.call::<_, _, ()>(method, &()).await,
+                _ => proxy.call::<_, _, ()>(method, &(argument,)).await,
+            }
+            .unwrap_or_else(|e| panic!("{}('{}'): {}", method, argument, e));
+            let event = time::timeout(WAIT, events.recv()).await.unwrap().unwrap();
+            assert_eq!(format!("{:?}", event), expected, "{}('{}')", method, argument);
+        }
+        let error = proxy.call::<_, _, ()>("Pause", &("2h",)).await.unwrap_err();
+        assert!(error.to_string().contains("Unknown pause duration '2h'"), "{}", error);
+        assert!(events.try_recv().is_err());
+
+        // Properties follow the connection and delivered messages
+        assert_eq!(proxy.get_property::<String>("ConnectionState").await.unwrap(), "connecting");
+        status_tx.send_modify(|status| status.state = ConnectionState::LoggedOut);
+        assert_eq!(proxy.get_property::<String>("ConnectionState").await.unwrap(), "logged_out");
+
+        let mut received = proxy.receive_signal("MessageReceived").await.unwrap();
+        delivered
+            .send(Message {
+                id: 7,
+                app: "Backups".to_string(),
+                title: Some("Backup".to_string()),
+                message: "Nightly backup failed".to_string(),
+                priority: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        let signal = time::timeout(WAIT, received.next()).await.unwrap().unwrap();
+        let body: (i64, String, String, String, i32, String) = signal.body().deserialize().unwrap();
+        assert_eq!(
+            body,
+            (7, "Backups".into(), "Backup".into(), "Nightly backup failed".into(), 1, String::new())
+        );
+
+        // The signal goes out before the count is bumped
+        time::timeout(WAIT, async {
+            while proxy.get_property::<u32>("UnreadCount").await.unwrap() != 1 {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("unread count bumped");
+        proxy.call::<_, _, ()>("MarkRead", &()).await.unwrap();
+        assert_eq!(proxy.get_property::<u32>("UnreadCount").await.unwrap(), 0);
+
+        server.abort();
+    }
+}
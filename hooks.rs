@@ -0,0 +1,322 @@
+use crate::rules::{MatchSpec, Matcher};
+use crate::types::Message;
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::time;
+
+// How much of a failing hook's output ends up in the log
+const MAX_LOGGED_OUTPUT: usize = 2000;
+
+// A local command run for incoming messages
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfig {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    // Further runs are skipped while this many are still going
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    // Run for every message this matches; without it the hook only runs from rules
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matcher: Option<MatchSpec>,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_concurrent() -> usize {
+    1
+}
+
+struct Hook {
+    config: HookConfig,
+    matcher: Option<Matcher>,
+    slots: Arc<Semaphore>,
+}
+
+// Validated hooks from the config
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Arc<Hook>>,
+}
+
+impl Hooks {
+    pub fn new(configs: &[HookConfig]) -> Result<Self> {
+        let mut names = HashSet::new();
+        let mut hooks = Vec::new();
+
+        for config in configs {
+            let context = || format!("In hook '{}'", config.name);
+
+            if config.name.is_empty() {
+                return Err(anyhow!("Hook without a name"));
+            }
+            if !names.insert(config.name.as_str()) {
+                return Err(anyhow!("Duplicate hook '{}'", config.name));
+            }
+            if config.command.is_empty() {
+                return Err(anyhow!("Command is empty")).with_context(context);
+            }
+            if config.timeout_secs == 0 || config.max_concurrent == 0 {
+                return Err(anyhow!("timeout_secs and max_concurrent must be at least 1")).with_context(context);
+            }
+
+            let matcher = config.matcher.as_ref().map(Matcher::compile).transpose().with_context(context)?;
+            hooks.push(Arc::new(Hook {
+                config: config.clone(),
+                matcher,
+                slots: Arc::new(Semaphore::new(config.max_concurrent)),
+            }));
+        }
+
+        Ok(Self { hooks })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.hooks.iter().map(|hook| hook.config.name.as_str()).collect()
+    }
+
+    // Start every hook that matches the message or was requested by a rule,
+    // without waiting for them
+    pub fn dispatch(&self, message: &Message, requested: &[String]) {
+        for hook in &self.hooks {
+            let matched = hook.matcher.as_ref().is_some_and(|matcher| matcher.matches(message));
+            if !matched && !requested.contains(&hook.config.name) {
+                continue;
+            }
+
+            let Ok(permit) = hook.slots.clone().try_acquire_owned() else {
+                warn!(
+                    "Hook '{}' already running {} time(s), skipping message {}",
+                    hook.config.name, hook.config.max_concurrent, message.id
+                );
+                continue;
+            };
+
+            let (hook, message) = (hook.clone(), message.clone());
+            tokio::spawn(async move {
+                if let Err(e) = run(&hook.config, &message).await {
+                    error!("Hook '{}' failed for message {}: {:#}", hook.config.name, message.id, e);
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+async fn run(config: &HookConfig, message: &Message) -> Result<()> {
+    debug!("Running hook '{}' for message {}", config.name, message.id);
+
+    let mut command = Command::new(&config.command);
+    command
+        .args(&config.args)
+        .envs(environment(config, message))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    // Don't flash a console window for every run
+    #[cfg(windows)]
+    command.creation_flags(0x0800_0000); // CREATE_NO_WINDOW
+
+    let mut child = command.spawn().with_context(|| format!("Failed to start {}", config.command))?;
+
+    let payload = serde_json::to_vec(message)?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for hook"))?;
+    let write_stdin = async move {
+        // The hook may not read stdin at all, that's fine
+        if let Err(e) = stdin.write_all(&payload).await {
+            debug!("Hook did not take the JSON payload: {}", e);
+        }
+    };
+
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let output = match time::timeout(timeout, async { tokio::join!(write_stdin, child.wait_with_output()).1 }).await {
+        Ok(output) => output?,
+        // Dropping the child kills it
+        Err(_) => return Err(anyhow!("Timed out after {}s and was killed", config.timeout_secs)),
+    };
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Exited with {}: {}",
+            output.status,
+            truncate(&String::from_utf8_lossy(&output.stderr))
+        ));
+    }
+
+    info!("Hook '{}' finished for message {}", config.name, message.id);
+    if !output.stdout.is_empty() {
+        debug!("Hook '{}' output: {}", config.name, truncate(&String::from_utf8_lossy(&output.stdout)));
+    }
+    Ok(())
+}
+
+// Message fields as MINIOVER_* variables
+fn environment(config: &HookConfig, message: &Message) -> Vec<(&'static str, String)> {
+    vec![
+        ("MINIOVER_HOOK", config.name.clone()),
+        ("MINIOVER_ID", message.id_str.clone()),
+        ("MINIOVER_APP", message.app.clone()),
+        ("MINIOVER_AID", message.aid_str.clone()),
+        ("MINIOVER_TITLE", message.title.clone().unwrap_or_default()),
+        ("MINIOVER_MESSAGE", message.message.clone()),
+        ("MINIOVER_PRIORITY", message.priority.to_string()),
+        ("MINIOVER_DATE", message.date.to_string()),
+        ("MINIOVER_URL", message.url.clone().unwrap_or_default()),
+        ("MINIOVER_URL_TITLE", message.url_title.clone().unwrap_or_default()),
+        ("MINIOVER_SOUND", message.sound.clone().unwrap_or_default()),
+        ("MINIOVER_RECEIPT", message.receipt.clone().unwrap_or_default()),
+    ]
+}
+
+fn truncate(text: &str) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(MAX_LOGGED_OUTPUT) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::time::Instant;
+
+    // Runs `script` with `sh -c`, the output file as $1
+    fn hook(script: &str, output: &Path) -> HookConfig {
+        HookConfig {
+            name: "test".to_string(),
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string(), "sh".to_string(), output.display().to_string()],
+            timeout_secs: 5,
+            max_concurrent: 1,
+            matcher: None,
+        }
+    }
+
+    fn message() -> Message {
+        Message {
+            id: 7,
+            id_str: "7".to_string(),
+            message: "Disk full\non /backup".to_string(),
+            app: "Backups".to_string(),
+            aid_str: "42".to_string(),
+            date: 1700000000,
+            priority: 2,
+            title: Some("Nightly backup failed".to_string()),
+            url: Some("https://example.com/log?run=1&v=2".to_string()),
+            receipt: Some("r1234".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_the_message_as_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("env");
+        let expected = [
+            ("MINIOVER_HOOK", "test"),
+            ("MINIOVER_ID", "7"),
+            ("MINIOVER_APP", "Backups"),
+            ("MINIOVER_AID", "42"),
+            ("MINIOVER_TITLE", "Nightly backup failed"),
+            ("MINIOVER_MESSAGE", "Disk full\non /backup"),
+            ("MINIOVER_PRIORITY", "2"),
+            ("MINIOVER_DATE", "1700000000"),
+            ("MINIOVER_URL", "https://example.com/log?run=1&v=2"),
+            ("MINIOVER_URL_TITLE", ""),
+            ("MINIOVER_SOUND", ""),
+            ("MINIOVER_RECEIPT", "r1234"),
+        ];
+        let variables: Vec<String> = expected.iter().map(|(name, _)| format!("\"${}\"", name)).collect();
+        let script = format!("printf '%s\\0' {} > \"$1\"", variables.join(" "));
+
+        run(&hook(&script, &output), &message()).await.unwrap();
+        let written = fs::read_to_string(&output).unwrap();
+        let values: Vec<&str> = written.trim_end_matches('\0').split('\0').collect();
+        for ((name, value), written) in expected.iter().zip(&values) {
+            assert_eq!(written, value, "{}", name);
+        }
+        assert_eq!(values.len(), expected.len());
+    }
+
+    #[tokio::test]
+    async fn passes_the_message_as_json_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("stdin");
+
+        run(&hook("cat > \"$1\"", &output), &message()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+        assert_eq!(json, serde_json::to_value(message()).unwrap());
+
+        // Not reading it is fine too
+        run(&hook("true", &output), &message()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = run(&hook("echo 'no space left' >&2; exit 3", dir.path()), &message())
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Exited with exit status: 3"), "{}", error);
+        assert!(error.ends_with("no space left"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn kills_hooks_that_time_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("finished");
+        let config = HookConfig {
+            timeout_secs: 1,
+            ..hook("sleep 2; touch \"$1\"", &output)
+        };
+
+        let started = Instant::now();
+        let error = run(&config, &message()).await.unwrap_err();
+        assert_eq!(error.to_string(), "Timed out after 1s and was killed");
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        time::sleep(Duration::from_millis(1500)).await;
+        assert!(!output.exists(), "hook kept running");
+    }
+
+    #[tokio::test]
+    async fn skips_runs_beyond_max_concurrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("runs");
+        let hooks = Hooks::new(&[hook("echo \"$MINIOVER_ID\" >> \"$1\"; sleep 1", &output)]).unwrap();
+        let requested = ["test".to_string()];
+        let numbered = |id: i64| Message {
+            id,
+            id_str: id.to_string(),
+            ..Default::default()
+        };
+
+        hooks.dispatch(&numbered(1), &requested);
+        hooks.dispatch(&numbered(2), &requested);
+        time::sleep(Duration::from_millis(1500)).await;
+        hooks.dispatch(&numbered(3), &requested);
+        time::sleep(Duration::from_millis(500)).await;
+
+        assert_eq!(fs::read_to_string(&output).unwrap(), "1\n3\n");
+    }
+}
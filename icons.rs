@@ -0,0 +1,54 @@
+use crate::types::ConnectionState;
+use image::{Rgba, RgbaImage};
+use log::error;
+
+// Embed the PNG icon at compile time
+const ICON_PNG: &[u8] = include_bytes!("icon.png");
+
+const BADGE_AMBER: Rgba<u8> = Rgba([245, 166, 35, 255]);
+const BADGE_RED: Rgba<u8> = Rgba([220, 53, 69, 255]);
+
+// Tray icon for a connection state, derived at runtime from the embedded PNG
+pub fn render(state: ConnectionState) -> Option<RgbaImage> {
+    let icon = match image::load_from_memory(ICON_PNG) {
+        Ok(img) => img.into_rgba8(),
+        Err(e) => {
+            error!("Failed to decode embedded icon: {}", e);
+            return None;
+        }
+    };
+
+    Some(match state {
+        ConnectionState::Connected => icon,
+        ConnectionState::Connecting | ConnectionState::Reconnecting => faded(icon),
+        ConnectionState::Polling => badged(icon, BADGE_AMBER),
+        ConnectionState::LoggedOut => badged(faded(icon), BADGE_RED),
+    })
+}
+
+// Greyscale and partly transparent, for "not connected (yet)"
+fn faded(mut icon: RgbaImage) -> RgbaImage {
+    for pixel in icon.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
+        *pixel = Rgba([luma, luma, luma, (a as f32 * 0.6) as u8]);
+    }
+    icon
+}
+
+// Filled dot with a white rim in the bottom-right corner
+fn badged(mut icon: RgbaImage, colour: Rgba<u8>) -> RgbaImage {
+    let (width, height) = icon.dimensions();
+    let radius = width.min(height) as f32 / 5.0;
+    let (cx, cy) = (width as f32 - radius - 1.0, height as f32 - radius - 1.0);
+
+    for (x, y, pixel) in icon.enumerate_pixels_mut() {
+        let distance = ((x as f32 + 0.5 - cx).powi(2) + (y as f32 + 0.5 - cy).powi(2)).sqrt();
+        if distance <= radius - 1.0 {
+            *pixel = colour;
+        } else if distance <= radius {
+            *pixel = Rgba([255, 255, 255, 255]);
+        }
+    }
+    icon
+}
@@ -0,0 +1,37 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+
+const LOCK_FILENAME: &str = "miniover.lock";
+
+// Held for the lifetime of the tray client; the OS drops the lock when the process exits,
+// so a crash can't leave a stale one behind
+pub struct InstanceLock {
+    _file: File,
+}
+
+// Take the per-user instance lock, None if another miniover already holds it
+pub fn acquire(state_dir: &Path) -> Result<Option<InstanceLock>> {
+    let lock_path = state_dir.join(LOCK_FILENAME);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(None),
+        Err(TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()));
+        }
+    }
+
+    // Only informational, the lock itself is what counts
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+
+    Ok(Some(InstanceLock { _file: file }))
+}
@@ -14,38 +14,141 @@ mod types;
 mod creds;
 mod utils;
 mod tray;
+mod outbox;
+mod cli;
+mod status;
+mod net;
+mod tls;
+mod power;
+mod icons;
+mod systray;
+mod pause;
+mod quiet;
+mod rules;
+mod hooks;
+mod webhook;
+mod mqtt;
+mod control;
+mod instance;
+mod systemd;
+mod secrets;
+mod migrate;
+mod settings;
+#[cfg(target_os = "linux")]
+mod dbus;
 
-use tokio::sync::mpsc;
-use anyhow::{Result, Error};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use anyhow::{anyhow, Result, Error};
 use ftail::Ftail;
-use log::{debug, info, error, LevelFilter};
+use log::{debug, info, warn, error, LevelFilter};
+use std::future::Future;
 use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use types::{Event, AppState};
-use tray_item::{IconSource, TrayItem};
-use utils::{get_app_paths, init_config};
+use types::{Event, AppState, ConnectionStatus};
+use systray::SysTray;
+use utils::init_config;
 use std::sync::mpsc as std_mpsc;
-use std::sync::Mutex as StdMutex;
+use cli::Command;
+
+// How long the feed gets to close the WebSocket and flush the outbox when quitting
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+// How often the bridge thread looks up from the menu channel to check for shutdown
+const BRIDGE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Quit on Ctrl+C, and on SIGTERM from systemd or `kill` on Unix
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Interrupted, shutting down"),
+        _ = terminate => info!("Terminated, shutting down"),
+        _ = shutdown.cancelled() => return,
+    }
+    shutdown.cancel();
+}
+
+// Run one of the main tasks; if it stops before a shutdown was requested, bring the
+// rest of miniover down with it
+fn spawn_supervised<F>(name: &'static str, shutdown: CancellationToken, task: F) -> JoinHandle<Result<()>>
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let result = task.await;
+        if shutdown.is_cancelled() {
+            return result;
+        }
+
+        error!("{} exited unexpectedly: {:?}", name, result);
+        shutdown.cancel();
+        Err(result.err().unwrap_or_else(|| anyhow!("{} exited unexpectedly", name)))
+    })
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    // Get application paths
-    let (config_dir, log_dir) = get_app_paths();
-    
-    // Initialize logging with concrete path
+    // Handle CLI subcommands before starting the tray client
+    let args = cli::parse_args()?;
+    if !matches!(args.command, Command::Run) {
+        cli::attach_parent_console();
+    }
+    if let Command::Help = args.command {
+        cli::print_usage();
+        return Ok(());
+    }
+    // Everything else needs to know where miniover keeps its files
+    let paths = utils::init_paths(&args.dirs)?;
+    match args.command {
+        Command::Run | Command::Help => {}
+        Command::Status => return status::print_status(&args.overrides, &quiet::SystemClock),
+        Command::ShowConfig { effective } => return cli::show_config(&args.overrides, effective),
+        Command::Control(request) => return cli::run_control(&request),
+    }
+
+    // Only one tray client per user, a second one would get its session closed by Pushover
+    let Some(_instance_lock) = instance::acquire(&paths.state_dir)? else {
+        cli::attach_parent_console();
+        println!("Miniover is already running");
+        toast::show_success_notification("Miniover", "Miniover is already running").ok();
+        return Ok(());
+    };
+
+    // Initialize logging with concrete path; what actually gets through is
+    // the log_level setting, applied by init_config
     Ftail::new()
-        .console(LevelFilter::Debug)
-        .daily_file(&log_dir, LevelFilter::Debug) 
+        .console(LevelFilter::Trace)
+        .daily_file(&paths.log_dir, LevelFilter::Trace)
         .max_file_size(1024 * 1024 * 10) // 10MB
         .retention_days(2) // 2 days
         .init()?;
     
     info!("Miniover starting up");
-    info!("Config directory: {:?}", config_dir);
-    info!("Log directory: {:?}", log_dir);
+    info!("Config directory: {:?}", paths.config_dir);
+    info!("State directory: {:?}", paths.state_dir);
+    info!("Log directory: {:?}", paths.log_dir);
+    utils::move_legacy_state(paths);
     
     // Initialize config and handle login
-    let config = match init_config().await {
+    let config = match init_config(&args.overrides).await {
         Ok(config) => config,
         Err(e) => {
             error!("Failed to initialize: {}", e);
@@ -57,166 +160,111 @@ async fn main() -> Result<(), Error> {
     let (tokio_tx, tokio_rx) = mpsc::channel::<Event>(100);
     
     // Initialize app state
-    let app_state = Arc::new(Mutex::new(AppState {
-        config,
-    }));
+    let app_state = Arc::new(Mutex::new(AppState::new(config)));
         
     debug!("App state: {:?}", app_state);
 
+    // Cancelled by Quit, Logout, a signal or a main task dying; everything winds down from here
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
     // We'll use a direct std::thread to handle bridge events 
     // This ensures we keep a direct thread for processing UI callbacks
-    let tokio_tx_clone = tokio_tx.clone();
+    let tokio_tx_for_thread = tokio_tx.clone();
+    let bridge_shutdown = shutdown.clone();
     let (std_tx, std_rx) = std_mpsc::channel::<Event>();
     
-    // Wrap the tokio sender in an Arc<Mutex> so it can be shared safely across threads
-    let tokio_tx_for_thread = Arc::new(StdMutex::new(tokio_tx_clone));
-    let tokio_tx_clone_for_thread = tokio_tx_for_thread.clone();
-    
     // Spawn a std::thread to bridge events (this is different from tokio::spawn)
     std::thread::spawn(move || {
         info!("Bridge thread started");
-        let runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create runtime");
-        
-        runtime.block_on(async {
-            while let Ok(event) = std_rx.recv() {
-                // Get the tokio sender from the mutex
-                let sender = tokio_tx_clone_for_thread.lock().unwrap();
-                if let Err(e) = sender.send(event).await {
-                    error!("Bridge failed to send event: {}", e);
+        loop {
+            match std_rx.recv_timeout(BRIDGE_POLL_INTERVAL) {
+                Ok(event) => {
+                    if let Err(e) = tokio_tx_for_thread.blocking_send(event) {
+                        error!("Bridge failed to send event: {}", e);
+                        break;
+                    }
                 }
-            }
-            
-            error!("Bridge thread receiver closed unexpectedly");
-        });
-    });
-
-    // Platform-specific tray icon source
-    #[cfg(windows)]
-    let icon_source = IconSource::Resource("app-icon");
-    
-    // On Linux, embed the icon in the binary and decode to RGBA data
-    #[cfg(target_os = "linux")]
-    let icon_source = {
-        use image::GenericImageView;
-        
-        // Embed the PNG icon at compile time
-        const ICON_PNG: &[u8] = include_bytes!("icon.png");
-        
-        // Decode PNG to RGBA pixels
-        match image::load_from_memory(ICON_PNG) {
-            Ok(img) => {
-                let (width, height) = img.dimensions();
-                let rgba = img.into_rgba8();
-                let data = rgba.into_raw();
-                
-                debug!("Tray icon loaded: {}x{}", width, height);
-                IconSource::Data {
-                    width: width as i32,
-                    height: height as i32,
-                    data,
+                Err(RecvTimeoutError::Timeout) if bridge_shutdown.is_cancelled() => break,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    error!("Bridge thread receiver closed unexpectedly");
+                    break;
                 }
             }
-            Err(e) => {
-                error!("Failed to decode embedded icon: {}, using fallback", e);
-                // Fallback to a common system icon
-                IconSource::Resource("dialog-information")
-            }
         }
-    };
+        debug!("Bridge thread stopped");
+    });
 
     // Fail compilation on unsupported targets with a clear error message
     #[cfg(not(any(windows, target_os = "linux")))]
     compile_error!("Unsupported target OS: only Windows and Linux are supported");
 
-    // Create menu
-    let mut tray = TrayItem::new(
-        "Miniover",
-        icon_source,
-    )?;
-
-    debug!("Tray created successfully");
-
-    // Create menu items
-    let state = app_state.lock().await.config.clone();
-
-    // Create text for menu items
-    let toggle_text = match state.start_on_boot {
-        true => "Start on boot [✓]",
-        false => "Start on boot [ ]",
-    };
-
-    let toggle_startup_tx = std_tx.clone();
-    let toggle_startup_menu_item_id = tray.inner_mut().add_menu_item_with_id(toggle_text, move || {
-        if let Err(e) = toggle_startup_tx.send(Event::ToggleStartOnBoot) {
-            error!("Failed to send toggle startup event: {:?}", e);
-        }
-    })?;
-
-    debug!("Toggle startup menu item added successfully");
-
-    tray.inner_mut().add_separator()?;
-
-    let show_logs_tx = std_tx.clone();
-    tray.add_menu_item("Show Logs", move || {
-        if let Err(e) = show_logs_tx.send(Event::ShowLogs) {
-            error!("Failed to send show logs event: {:?}", e);
-        }
-    })?;
-
-    debug!("Show logs menu item added successfully");
-
-    let about_tx = std_tx.clone();
-    tray.add_menu_item("About", move || {
-        if let Err(e) = about_tx.send(Event::ShowAbout) {
-            error!("Failed to send about event: {:?}", e);
-        }
-    })?;
-
-    debug!("About menu item added successfully");
-
-    let quit_tx = std_tx.clone();
-    tray.add_menu_item("Quit", move || {
-        if let Err(e) = quit_tx.send(Event::Quit) {
-            error!("Failed to send quit event: {:?}", e);
-        }
-    })?;
-
-    debug!("Quit menu item added successfully");
-
-    let logout_tx = std_tx.clone();
-    tray.add_menu_item("Logout", move || {
-        if let Err(e) = logout_tx.send(Event::Logout) {
-            error!("Failed to send logout event: {:?}", e);
-        }
-    })?;
-
-    debug!("Logout menu item added successfully");
+    // Create tray, its icon follows the connection state published by the feed and
+    // its menu is rebuilt by the tray task as things change
+    let tray = SysTray::new("Miniover", std_tx.clone())?;
     
     info!("Tray icon created successfully");
         
     // Spawn message handling with its own channel
-    let message_handle = tokio::spawn(messages::consume_message_feed());
-    let tray_handle = tokio::spawn(tray::consume_tray_events(
+    let (status_tx, status_rx) = watch::channel(ConnectionStatus::default());
+    let (feed_tx, feed_rx) = mpsc::channel(100);
+    let (delivered_tx, _) = broadcast::channel(100);
+    
+    // The CLI and local scripts drive miniover through a socket (a named pipe on Windows)
+    control::start(control::Control {
+        app_state: app_state.clone(),
+        status_rx: status_rx.clone(),
+        events: tokio_tx.clone(),
+        delivered: delivered_tx.clone(),
+    });
+    
+    // Desktop widgets and status bars can use the session bus instead
+    #[cfg(target_os = "linux")]
+    dbus::start(dbus::DbusContext {
+        app_state: app_state.clone(),
+        status_rx: status_rx.clone(),
+        events: tokio_tx.clone(),
+        delivered: delivered_tx.clone(),
+    });
+    
+    // Edits to settings.toml and rules.json are applied without a restart
+    settings::spawn_watcher(paths.config_dir.clone(), args.overrides, feed_tx.clone(), shutdown.clone());
+    
+    let message_handle = spawn_supervised("Message handler", shutdown.clone(), messages::consume_message_feed(
+        app_state.clone(),
+        status_tx,
+        tokio_tx.clone(),
+        delivered_tx,
+        feed_rx,
+        shutdown.clone(),
+    ));
+    let tray_handle = spawn_supervised("Tray handler", shutdown.clone(), tray::consume_tray_events(
         tokio_rx, 
+        status_rx,
         app_state.clone(), 
-        tray::TrayContext {
-            tray,
-            toggle_startup_menu_item_id,
-        }
+        feed_tx,
+        tray::TrayContext::new(tray),
+        shutdown.clone(),
     ));
 
-    // Wait for tasks to complete
-    tokio::select! {
-        result = message_handle => {
-            error!("Message handler exited: {:?}", result);
-            Err(anyhow::anyhow!("Message handler exited unexpectedly"))
-        },
-        result = tray_handle => {
-            error!("Tray handler exited: {:?}", result);
-            Err(anyhow::anyhow!("Tray handler exited unexpectedly"))
+    // Run until something asks us to stop, then let the tasks finish up
+    shutdown.cancelled().await;
+    info!("Shutting down");
+    
+    let results = match time::timeout(SHUTDOWN_TIMEOUT, async { tokio::join!(message_handle, tray_handle) }).await {
+        Ok((message_result, tray_result)) => [message_result, tray_result],
+        Err(_) => {
+            warn!("Tasks did not finish within {}s, exiting anyway", SHUTDOWN_TIMEOUT.as_secs());
+            log::logger().flush();
+            return Ok(());
         }
+    };
+    
+    info!("Miniover stopped");
+    log::logger().flush();
+    for result in results {
+        result??;
     }
+    Ok(())
 }
@@ -1,33 +1,50 @@
-use crate::types::{Config, Message, MessagesResponse};
-use crate::toast;
-use anyhow::{anyhow, Result};
+use crate::types::{AckPolicy, AppState, Config, ConnectionState, ConnectionStatus, Event, Message, MessagesResponse, Preferences, Transport};
+use crate::status::RuntimeStatus;
+use crate::power::{self, WakeReason};
+use crate::outbox::{Outbox, PendingAction};
+use crate::net::{api_url, connect_tcp, http_client, websocket_url, ws_connector};
+use crate::pause;
+use crate::quiet::{Clock, QuietDecision, SystemClock};
+use crate::rules::Rules;
+use crate::hooks::Hooks;
+use crate::webhook::Webhooks;
+use crate::mqtt::{self, Mqtt};
+use crate::secrets;
+use crate::settings;
+use crate::systemd::Notifier;
+use crate::toast::{self, NotifyOptions};
+use anyhow::{anyhow, Context, Result};
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
-use reqwest::Client;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
 use tokio::time;
+use tokio_util::sync::{CancellationToken, DropGuard};
 use tokio_tungstenite::{
-    connect_async_tls_with_config, 
+    client_async_tls_with_config, 
     tungstenite::protocol::Message as WsMessage,
-    Connector,
     MaybeTlsStream,
     WebSocketStream
 };
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
-use crate::utils::{get_app_config_dir, save_config, load_config};
+use crate::utils::{app_paths, save_config, unix_now};
 
-const PUSHOVER_API_URL: &str = "https://api.pushover.net/1";
-const PUSHOVER_WS_URL: &str = "wss://client.pushover.net/push";
 const RECONNECT_DELAY_MS: u64 = 5000;
+const MIN_POLL_INTERVAL_SECS: u64 = 10;
+const WEBSOCKET_PROBE_INTERVAL_SECS: u64 = 5 * 60;
+// Time limits while quitting; together they stay under the main shutdown timeout
+const WEBSOCKET_CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+const OUTBOX_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 // Function to download messages from Pushover API
 pub async fn download_messages(secret: &str, device_id: &str) -> Result<Vec<Message>> {
-    let client = Client::new();
+    let client = http_client();
     let url = format!(
         "{}/messages.json?secret={}&device_id={}",
-        PUSHOVER_API_URL, secret, device_id
+        api_url(), secret, device_id
     );
 
     debug!("Downloading messages from: {}", url);
@@ -55,10 +72,10 @@ pub async fn download_messages(secret: &str, device_id: &str) -> Result<Vec<Mess
 
 // Function to delete messages from Pushover API
 pub async fn delete_messages(secret: &str, device_id: &str, message_id: &str) -> Result<()> {
-    let client = Client::new();
+    let client = http_client();
     let url = format!(
         "{}/devices/{}/update_highest_message.json",
-        PUSHOVER_API_URL, device_id
+        api_url(), device_id
     );
     
     let form = [
@@ -83,10 +100,10 @@ pub async fn delete_messages(secret: &str, device_id: &str, message_id: &str) ->
 
 // Function to acknowledge emergency priority messages
 pub async fn acknowledge_emergency(secret: &str, receipt: &str) -> Result<()> {
-    let client = Client::new();
+    let client = http_client();
     let url = format!(
         "{}/receipts/{}/acknowledge.json",
-        PUSHOVER_API_URL, receipt
+        api_url(), receipt
     );
     
     let form = [
@@ -108,69 +125,36 @@ pub async fn acknowledge_emergency(secret: &str, receipt: &str) -> Result<()> {
     Ok(())
 }
 
-// Function to process incoming messages
-async fn process_messages(config: &mut Config, config_dir: &Path) -> Result<()> {
-    // Guard against missing credentials
-    if config.secret.is_none() || config.device_id.is_none() {
-        return Err(anyhow!("Missing secret or device ID"));
+// Retry any pending outbox actions and persist the result
+async fn flush_outbox(config: &Config, state_dir: &Path, outbox: &mut Outbox) {
+    if outbox.flush(config).await && let Err(e) = outbox.save(state_dir) {
+        error!("Failed to save outbox: {}", e);
     }
-    
-    let secret = config.secret.as_ref().unwrap();
-    let device_id = config.device_id.as_ref().unwrap();
-    
-    // Download messages
-    debug!("Downloading messages");
-    let messages = download_messages(secret, device_id).await?;
-    
-    if messages.is_empty() {
-        return Ok(());
-    }
-    
-    // Get highest message ID
-    debug!("Getting highest message ID");
-    let highest_message = messages.iter().max_by_key(|m| m.id).unwrap();
-    
-    // Process each message
-    debug!("Processing messages");
-    for message in &messages {
-        // Show notification
-        if let Err(e) = toast::show_notification(message) {
-            error!("Failed to show notification: {}", e);
-        }
-        
-        // If emergency priority, acknowledge it
-        if message.priority >= 2 && message.acked == 0 {
-            if let Some(receipt) = &message.receipt {
-                if let Err(e) = acknowledge_emergency(secret, receipt).await {
-                    error!("Failed to acknowledge emergency message: {}", e);
-                }
-            }
-        }
-    }
-    
-    // Delete messages from server
-    if let Err(e) = delete_messages(secret, device_id, &highest_message.id_str).await {
-        error!("Failed to delete messages: {}", e);
-    } else {
-        // Update config with last message ID
-        config.last_message_id = Some(highest_message.id_str.clone());
-        save_config(config, config_dir)?;
+}
+
+// Sleep until the next outbox retry is due, or forever if there is nothing queued
+async fn outbox_retry_due(outbox: &Outbox) {
+    match outbox.next_due() {
+        Some(delay) => time::sleep(delay).await,
+        None => std::future::pending::<()>().await,
     }
-    
-    Ok(())
 }
 
 // Function to establish WebSocket connection and handle messages
-async fn connect_websocket(config: &Config) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
-    let request = PUSHOVER_WS_URL.into_client_request()?;
+async fn connect_websocket(config: &Config) -> Result<WsStream> {
+    let url = reqwest::Url::parse(&websocket_url())?;
+    let host = url.host_str().ok_or_else(|| anyhow!("WebSocket URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let request = url.as_str().into_client_request()?;
+    
+    // Open the TCP connection ourselves so it can go through a proxy
+    let stream = connect_tcp(host, port).await?;
     
-    let (ws_stream, _) = connect_async_tls_with_config(
+    let (ws_stream, _) = client_async_tls_with_config(
         request,
+        stream,
         None,
-        false,
-        Some(Connector::NativeTls(
-            native_tls::TlsConnector::builder().build()?,
-        )),
+        ws_connector(),
     )
     .await?;
     
@@ -180,8 +164,8 @@ async fn connect_websocket(config: &Config) -> Result<WebSocketStream<MaybeTlsSt
     let mut ws_stream = ws_stream;
     let login_msg = format!(
         "login:{}:{}\n",
-        config.device_id.as_ref().unwrap(),
-        config.secret.as_ref().unwrap()
+        config.credentials.device_id.as_ref().unwrap(),
+        config.credentials.secret.as_ref().unwrap()
     );
     
     ws_stream.send(WsMessage::Text(login_msg.into())).await?;
@@ -189,126 +173,693 @@ async fn connect_websocket(config: &Config) -> Result<WebSocketStream<MaybeTlsSt
     Ok(ws_stream)
 }
 
-pub async fn consume_message_feed() -> Result<()> {
-    let config_dir = get_app_config_dir();
-    let mut config = load_config(&config_dir)?;
+// Invalid hooks or rules are reported and ignored rather than keeping miniover from starting
+fn load_hooks(config: &Config) -> Hooks {
+    Hooks::new(&config.preferences.hooks).unwrap_or_else(|e| {
+        error!("Invalid hooks, continuing without them: {:#}", e);
+        toast::show_error_notification("Invalid hooks", &format!("{:#}", e)).ok();
+        Hooks::default()
+    })
+}
+
+fn start_webhooks(config: &Config, state_dir: &Path) -> Webhooks {
+    Webhooks::start(&config.preferences.webhooks, state_dir).unwrap_or_else(|e| {
+        error!("Invalid webhooks, continuing without them: {:#}", e);
+        toast::show_error_notification("Invalid webhooks", &format!("{:#}", e)).ok();
+        Webhooks::default()
+    })
+}
+
+fn start_mqtt(config: &Config, status_tx: &watch::Sender<ConnectionStatus>, events: &mpsc::Sender<Event>) -> Mqtt {
+    Mqtt::start(config.preferences.mqtt.as_ref(), status_tx.subscribe(), events.clone()).unwrap_or_else(|e| {
+        error!("Invalid MQTT config, continuing without it: {:#}", e);
+        toast::show_error_notification("Invalid MQTT config", &format!("{:#}", e)).ok();
+        Mqtt::default()
+    })
+}
+
+fn load_rules(config_dir: &Path, hooks: &Hooks, webhooks: &Webhooks) -> Rules {
+    Rules::load(config_dir, &hooks.names(), &webhooks.names()).unwrap_or_else(|e| {
+        error!("Failed to load rules, continuing without them: {:#}", e);
+        toast::show_error_notification("Invalid rules", &format!("{:#}", e)).ok();
+        Rules::default()
+    })
+}
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Requests from the tray and remote controls that only the feed can carry out
+#[derive(Debug)]
+pub enum FeedCommand {
+    Acknowledge(String),
+    Reconnect,
+    // settings.toml or rules.json was edited
+    ApplySettings(Box<Preferences>),
+}
+
+// State owned by the message feed task
+struct Feed {
+    // Shared with the tray; `config` is the feed's snapshot of it
+    app_state: Arc<Mutex<AppState>>,
+    config: Config,
+    config_dir: PathBuf,
+    state_dir: PathBuf,
+    outbox: Outbox,
+    transport: Transport,
+    keepalive_count: u32,
+    wake_rx: mpsc::Receiver<WakeReason>,
+    commands: mpsc::Receiver<FeedCommand>,
+    status: ConnectionStatus,
+    status_tx: watch::Sender<ConnectionStatus>,
+    events: mpsc::Sender<Event>,
+    // Live stream of delivered messages for remote control clients
+    delivered_tx: broadcast::Sender<Message>,
+    clock: Box<dyn Clock>,
+    rules: Rules,
+    hooks: Hooks,
+    webhooks: Webhooks,
+    mqtt: Mqtt,
+    systemd: Notifier,
+    // Keeps the systemd watchdog pinged for as long as the feed exists
+    _watchdog: Option<DropGuard>,
+    shutdown: CancellationToken,
+}
+
+// Why a WebSocket session ended
+enum SessionEnd {
+    Disconnected,
+    // Suspend/resume or network change: resync and reconnect right away
+    Woken,
+    // miniover is quitting, the socket has been closed
+    Shutdown,
+}
+
+impl Feed {
+    fn logged_in(&self) -> bool {
+        self.config.credentials.secret.is_some() && self.config.credentials.device_id.is_some()
+    }
     
-    if config.secret.is_none() || config.device_id.is_none() {
-        panic!("Not logged in, login flow was disrupted");
-        // ! we should be logged in by now, so this is a bug
+    fn set_transport(&mut self, transport: Transport) {
+        if self.transport != transport {
+            info!("Switching transport: {} -> {}", self.transport, transport);
+            self.transport = transport;
+            self.status.transport = transport;
+            self.publish_status();
+        }
     }
     
-    if let Err(e) = process_messages(&mut config, &config_dir).await {
-        error!("Failed to process existing messages: {}", e);
+    fn set_state(&mut self, state: ConnectionState) {
+        if matches!(state, ConnectionState::Connected | ConnectionState::Polling) {
+            self.status.last_connected = Some(unix_now());
+        }
+        
+        if self.status.state != state {
+            info!("Connection state: {} -> {}", self.status.state, state);
+            self.status.state = state;
+            self.publish_status();
+        }
     }
     
-    // Counter for keep-alive packets to reduce logging
-    let mut keepalive_count = 0;
-    let keepalive_log_interval = 30; // every 10 "interval" = 5 minutes (30 seconds per ping)
+    fn record_error(&mut self, error: String) {
+        self.status.last_error = Some(error);
+        self.publish_status();
+    }
     
-    loop {
-        // Make sure we have credentials
-        if config.secret.is_none() || config.device_id.is_none() {
-            error!("Missing credentials for WebSocket connection");
-            time::sleep(Duration::from_millis(RECONNECT_DELAY_MS)).await;
-            continue;
+    // Let the tray and `miniover status` know about the current connection state
+    fn publish_status(&mut self) {
+        self.status_tx.send_replace(self.status.clone());
+        self.systemd.status(&self.status_text());
+        
+        let status = RuntimeStatus::new(self.status.clone());
+        if let Err(e) = status.save(&self.state_dir) {
+            error!("Failed to save runtime status: {}", e);
         }
+    }
+    
+    // e.g. "Connected (polling)" or "Reconnecting: WebSocket error: ..."
+    fn status_text(&self) -> String {
+        match (&self.status.state, &self.status.last_error) {
+            (ConnectionState::Connected | ConnectionState::Polling, _) => self.status.state.to_string(),
+            (state, Some(error)) => format!("{}: {}", state, error),
+            (state, None) => state.to_string(),
+        }
+    }
+    
+    async fn clear_credentials(&mut self) {
+        let mut state = self.app_state.lock().await;
+        state.config.credentials.secret = None;
+        state.config.credentials.device_id = None;
+        secrets::clear_credentials(&self.config_dir);
+        if let Err(e) = save_config(&state.config, &self.state_dir) {
+            error!("Failed to save config: {}", e);
+        }
+        self.config = state.config.clone();
+    }
+    
+    // Pick up config changes made elsewhere (logout, pause, ...)
+    async fn sync_config(&mut self) {
+        self.config = self.app_state.lock().await.config.clone();
+    }
+    
+    // Download new messages, show them and queue the server-side updates
+    async fn try_process_messages(&mut self) -> Result<()> {
+        self.sync_config().await;
+        
+        // Guard against missing credentials
+        let (Some(secret), Some(device_id)) = (&self.config.credentials.secret, &self.config.credentials.device_id) else {
+            return Err(anyhow!("Missing secret or device ID"));
+        };
+        
+        // Download messages
+        debug!("Downloading messages");
+        let messages = download_messages(secret, device_id).await?;
+        
+        // Skip messages we already showed but the server still holds (pending outbox update)
+        let last_seen = self.config.state.last_message_id.as_ref().and_then(|id| id.parse::<i64>().ok()).unwrap_or(0);
+        let messages: Vec<Message> = messages.into_iter().filter(|m| m.id > last_seen).collect();
+        
+        if messages.is_empty() {
+            return Ok(());
+        }
+        
+        // Get highest message ID
+        debug!("Getting highest message ID");
+        let highest_message_id = messages.iter().max_by_key(|m| m.id).unwrap().id_str.clone();
+        
+        // Run the rules first, they may rewrite or suppress messages
+        let mut delivered = Vec::new();
+        for message in &messages {
+            let mut message = message.clone();
+            let outcome = self.rules.apply(&mut message);
+            
+            if !outcome.matched.is_empty() {
+                debug!("Message {} matched {}", message.id, outcome.matched.join(", "));
+            }
+            
+            // Hooks, webhooks and MQTT run in the background, also for suppressed or held messages
+            self.hooks.dispatch(&message, &outcome.hooks);
+            self.webhooks.dispatch(&message, &outcome.sinks);
+            self.mqtt.publish(&message);
+            
+            if outcome.suppress {
+                info!("Message {} suppressed by rules", message.id);
+                continue;
+            }
+            delivered.push((message, outcome.options));
+        }
+        
+        // Decide what to show and record the rest, under the lock so a pause
+        // or quiet hours ending meanwhile can't lose them
+        let to_show: Vec<(&Message, NotifyOptions)> = {
+            let mut state = self.app_state.lock().await;
+            let (now, local_now) = (unix_now(), self.clock.now());
+            let mut to_show = Vec::new();
+            
+            for (message, options) in &delivered {
+                if pause::hold_if_paused(&mut state.config, message, now) {
+                    continue;
+                }
+                match state.config.preferences.quiet_hours.decide(message, &local_now) {
+                    QuietDecision::Show => to_show.push((message, options.clone())),
+                    QuietDecision::ShowSilently => to_show.push((message, NotifyOptions { silent: true, ..options.clone() })),
+                    QuietDecision::Hold => state.config.state.quiet_held.push(message.clone()),
+                }
+            }
+            
+            for (message, _) in &delivered {
+                state.recent.push_front(message.clone());
+            }
+            let keep = state.config.preferences.recent_messages;
+            state.recent.truncate(keep);
+            
+            // Messages are handled locally, record that even if the server hasn't been told yet
+            state.config.state.last_message_id = Some(highest_message_id.clone());
+            save_config(&state.config, &self.state_dir)?;
+            self.config = state.config.clone();
+            to_show
+        };
+        
+        if to_show.len() < delivered.len() {
+            info!("Held {} message(s) while paused or in quiet hours", delivered.len() - to_show.len());
+        }
+        
+        // Process each message
+        debug!("Processing messages");
+        for (message, options) in to_show {
+            // Show notification
+            if let Err(e) = toast::show_notification(message, &options) {
+                error!("Failed to show notification: {}", e);
+            }
+        }
+        
+        // If emergency priority, queue an acknowledgement unless the user wants to do that.
+        // Held messages too, or Pushover keeps retrying them until the pause or quiet hours end
+        for (message, _) in &delivered {
+            if self.config.preferences.ack_policy == AckPolicy::Auto
+                && message.priority >= 2
+                && message.acked == 0
+                && let Some(receipt) = &message.receipt
+            {
+                self.outbox.push(PendingAction::Acknowledge { receipt: receipt.clone() });
+            }
+        }
+        
+        // Queue deletion of messages from server
+        self.outbox.push(PendingAction::UpdateHighestMessage { message_id: highest_message_id });
+        self.outbox.save(&self.state_dir)?;
+        
+        flush_outbox(&self.config, &self.state_dir, &mut self.outbox).await;
+        
+        // No receivers just means nobody is subscribed
+        for (message, _) in delivered {
+            self.delivered_tx.send(message).ok();
+        }
+        
+        // Let the tray update its recent messages menu
+        if let Err(e) = self.events.send(Event::MessagesDelivered).await {
+            error!("Failed to send delivered messages to tray: {}", e);
+        }
+        Ok(())
+    }
+    
+    // Carry out a command, returns true if it asks for a reconnect
+    async fn handle_command(&mut self, command: FeedCommand) -> bool {
+        match command {
+            // Receipts end up in the request path, so only take what Pushover issues
+            FeedCommand::Acknowledge(receipt) if receipt.is_empty() || !receipt.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                warn!("Ignoring acknowledgement of invalid receipt {:?}", receipt);
+                false
+            }
+            FeedCommand::Acknowledge(receipt) => {
+                info!("Acknowledging receipt {}", receipt);
+                self.outbox.push(PendingAction::Acknowledge { receipt });
+                if let Err(e) = self.outbox.save(&self.state_dir) {
+                    error!("Failed to save outbox: {}", e);
+                }
+                flush_outbox(&self.config, &self.state_dir, &mut self.outbox).await;
+                false
+            }
+            FeedCommand::Reconnect => true,
+            FeedCommand::ApplySettings(preferences) => {
+                if let Err(e) = self.apply_settings(*preferences).await {
+                    settings::report_invalid(&e);
+                }
+                false
+            }
+        }
+    }
+    
+    // Switch to edited settings and rules. Everything is checked before anything changes,
+    // so a bad edit leaves the previous settings in place.
+    async fn apply_settings(&mut self, preferences: Preferences) -> Result<()> {
+        let hooks = Hooks::new(&preferences.hooks).context("Invalid hooks")?;
+        let webhook_names: Vec<&str> = preferences.webhooks.iter().map(|webhook| webhook.name.as_str()).collect();
+        let rules = Rules::load(&self.config_dir, &hooks.names(), &webhook_names)?;
+        Webhooks::check(&preferences.webhooks).context("Invalid webhooks")?;
+        if let Some(config) = &preferences.mqtt {
+            mqtt::validate(config).context("Invalid MQTT config")?;
+        }
+        
+        self.webhooks.reconfigure(&preferences.webhooks, &self.state_dir)?;
+        self.mqtt.reconfigure(preferences.mqtt.as_ref(), self.status_tx.subscribe(), self.events.clone()).await?;
+        self.hooks = hooks;
+        self.rules = rules;
+        log::set_max_level(preferences.log_level.into());
+        
+        let mut state = self.app_state.lock().await;
+        if settings::needs_restart(&state.config.preferences, &preferences) {
+            warn!("Changes to endpoints, proxy, tls and credentials take effect after a restart");
+        }
+        state.config.preferences = preferences;
+        self.config = state.config.clone();
+        info!("Settings reloaded");
+        Ok(())
+    }
+    
+    async fn process_messages(&mut self) {
+        if let Err(e) = self.try_process_messages().await {
+            error!("Failed to process messages: {}", e);
+        }
+    }
+    
+    // Give queued server updates one last try and leave the MQTT broker
+    async fn shut_down(self) {
+        let Feed { mut outbox, config, state_dir, mqtt, systemd, .. } = self;
+        systemd.stopping();
+        
+        if !outbox.is_empty() {
+            info!("Flushing {} pending outbox action(s) before quitting", outbox.entries.len());
+            outbox.retry_all_now();
+            // If we run out of time the outbox file is left as it was and retried on the next start
+            if time::timeout(OUTBOX_SHUTDOWN_TIMEOUT, flush_outbox(&config, &state_dir, &mut outbox)).await.is_err() {
+                warn!("Outbox flush timed out, pending actions will be retried on the next start");
+            } else if !outbox.is_empty() {
+                warn!("{} outbox action(s) still pending, retrying on the next start", outbox.entries.len());
+            }
+        }
+        
+        mqtt.shutdown().await;
+        if let Err(e) = RuntimeStatus::remove(&state_dir) {
+            error!("Failed to remove runtime status: {}", e);
+        }
+        info!("Message feed stopped");
+    }
+    
+    // Handle a connected WebSocket until it closes or errors
+    async fn run_websocket_session(&mut self, mut ws_stream: WsStream) -> SessionEnd {
+        // Log keep-alives only occasionally
+        let keepalive_log_interval = 30; // every 10 "interval" = 5 minutes (30 seconds per ping)
         
         // ! look into a way to have some sort of watchdog to check if the connection is still alive every 5 minutes or so
-
-        match connect_websocket(&config).await {
-            Ok(mut ws_stream) => {
-                info!("Connected to Pushover WebSocket");
-                
-                while let Some(msg) = ws_stream.next().await {
-                    match msg {
-                        Ok(WsMessage::Text(text)) => {
-                            debug!("Received text message: {}", text);
-                        }
-                        Ok(WsMessage::Binary(binary)) => {
-                            // Convert binary to string and process commands
-                            if binary.len() == 1 {
-                                let command = binary[0] as char;
-                                match command {
-                                    '#' => {
-                                        // Keep-alive packet, only log occasionally
-                                        keepalive_count += 1;
-                                        if keepalive_count % keepalive_log_interval == 0 {
-                                            debug!("Received keep-alive packet ({} received since last log)", keepalive_log_interval);
-                                        }
-                                    }
-                                    '!' => {
-                                        // New message arrived
-                                        info!("New message notification received");
-                                        if let Err(e) = process_messages(&mut config, &config_dir).await {
-                                            error!("Failed to process messages: {}", e);
-                                        }
-                                    }
-                                    'R' => {
-                                        // Reload request
-                                        info!("Reload request received, reconnecting...");
-                                        // ! implement reload logic
-                                        break;
-                                    }
-                                    'E' => {
-                                        // Error
-                                        error!("Permanent error received, need to re-login");
-                                        config.secret = None;
-                                        config.device_id = None;
-                                        if let Err(e) = save_config(&config, &config_dir) {
-                                            error!("Failed to save config: {}", e);
-                                        }
-                                        // tx.send(Event::Logout).await?; // figure out some way to logout
-                                        break;
-                                    }
-                                    'A' => {
-                                        // Session closed
-                                        warn!("Session closed, device logged in elsewhere");
-                                        config.secret = None;
-                                        config.device_id = None;
-                                        if let Err(e) = save_config(&config, &config_dir) {
-                                            error!("Failed to save config: {}", e);
-                                        }
-                                        // tx.send(Event::Logout).await?; // figure out some way to logout
-                                        // ! maybe add a toast notification here saying "Session closed, device logged in elsewhere" or something
-                                        break;
-                                    }
-                                    _ => {
-                                        warn!("Unknown WebSocket command: {}", command);
-                                    }
+        let shutdown = self.shutdown.clone();
+        loop {
+            let msg = tokio::select! {
+                msg = ws_stream.next() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = outbox_retry_due(&self.outbox) => {
+                    flush_outbox(&self.config, &self.state_dir, &mut self.outbox).await;
+                    continue;
+                }
+                Some(reason) = self.wake_rx.recv() => {
+                    // The old socket may look alive until TCP times out, don't wait for it
+                    info!("Dropping WebSocket connection: {}", reason);
+                    return SessionEnd::Woken;
+                }
+                Some(command) = self.commands.recv() => {
+                    if self.handle_command(command).await {
+                        info!("Dropping WebSocket connection: reconnect requested");
+                        return SessionEnd::Woken;
+                    }
+                    continue;
+                }
+                _ = shutdown.cancelled() => {
+                    info!("Closing WebSocket connection");
+                    // Send a close frame and wait for the server's, but don't hang on a dead socket
+                    let close = async {
+                        ws_stream.close(None).await?;
+                        while ws_stream.next().await.transpose()?.is_some() {}
+                        Ok::<_, tokio_tungstenite::tungstenite::Error>(())
+                    };
+                    match time::timeout(WEBSOCKET_CLOSE_TIMEOUT, close).await {
+                        Ok(Ok(())) => debug!("WebSocket closed cleanly"),
+                        Ok(Err(e)) => debug!("WebSocket close failed: {}", e),
+                        Err(_) => debug!("WebSocket close timed out"),
+                    }
+                    return SessionEnd::Shutdown;
+                }
+            };
+            
+            match msg {
+                Ok(WsMessage::Text(text)) => {
+                    debug!("Received text message: {}", text);
+                }
+                Ok(WsMessage::Binary(binary)) => {
+                    // Convert binary to string and process commands
+                    if binary.len() == 1 {
+                        let command = binary[0] as char;
+                        match command {
+                            '#' => {
+                                // Keep-alive packet, only log occasionally
+                                // The first one means Pushover accepted our login, which makes us ready
+                                self.systemd.ready();
+                                self.keepalive_count += 1;
+                                if self.keepalive_count.is_multiple_of(keepalive_log_interval) {
+                                    debug!("Received keep-alive packet ({} received since last log)", keepalive_log_interval);
                                 }
-                            } else {
-                                debug!("Received binary message: {:?}", binary);
-                                debug!("As string: {:?}", String::from_utf8_lossy(&binary));
+                            }
+                            '!' => {
+                                // New message arrived
+                                info!("New message notification received");
+                                self.process_messages().await;
+                            }
+                            'R' => {
+                                // Reload request
+                                info!("Reload request received, reconnecting...");
+                                // ! implement reload logic
+                                break;
+                            }
+                            'E' => {
+                                // Error
+                                error!("Permanent error received, need to re-login");
+                                self.record_error("Permanent error from Pushover, please log in again".to_string());
+                                self.set_state(ConnectionState::LoggedOut);
+                                self.clear_credentials().await;
+                                // tx.send(Event::Logout).await?; // figure out some way to logout
+                                break;
+                            }
+                            'A' => {
+                                // Session closed
+                                warn!("Session closed, device logged in elsewhere");
+                                self.record_error("Session closed, device logged in elsewhere".to_string());
+                                self.set_state(ConnectionState::LoggedOut);
+                                self.clear_credentials().await;
+                                // tx.send(Event::Logout).await?; // figure out some way to logout
+                                // ! maybe add a toast notification here saying "Session closed, device logged in elsewhere" or something
+                                break;
+                            }
+                            _ => {
+                                warn!("Unknown WebSocket command: {}", command);
                             }
                         }
-                        Ok(WsMessage::Ping(_)) => {
-                            debug!("Received ping");
-                        }
-                        Ok(WsMessage::Pong(_)) => {
-                            debug!("Received pong");
-                        }
-                        Ok(WsMessage::Close(close)) => {
-                            info!("WebSocket closed: {:?}", close);
-                            break;
-                        }
-                        Ok(WsMessage::Frame(frame)) => {
-                            debug!("Received frame: {:?}", frame);
+                    } else {
+                        debug!("Received binary message: {:?}", binary);
+                        debug!("As string: {:?}", String::from_utf8_lossy(&binary));
+                    }
+                }
+                Ok(WsMessage::Ping(_)) => {
+                    debug!("Received ping");
+                }
+                Ok(WsMessage::Pong(_)) => {
+                    debug!("Received pong");
+                }
+                Ok(WsMessage::Close(close)) => {
+                    info!("WebSocket closed: {:?}", close);
+                    self.record_error(format!("WebSocket closed: {:?}", close));
+                    break;
+                }
+                Ok(WsMessage::Frame(frame)) => {
+                    debug!("Received frame: {:?}", frame);
+                }
+                Err(e) => {
+                    error!("WebSocket error: {}", e);
+                    self.record_error(format!("WebSocket error: {}", e));
+                    break;
+                }
+            }
+        }
+        
+        SessionEnd::Disconnected
+    }
+    
+    // Poll the REST API until the WebSocket becomes reachable again, or None when quitting
+    async fn run_polling_session(&mut self) -> Option<WsStream> {
+        let poll_interval = Duration::from_secs(self.config.preferences.poll_interval_secs.max(MIN_POLL_INTERVAL_SECS));
+        info!("Polling for messages every {}s", poll_interval.as_secs());
+        
+        let mut poll_timer = time::interval(poll_interval);
+        let mut probe_timer = time::interval_at(
+            time::Instant::now() + Duration::from_secs(WEBSOCKET_PROBE_INTERVAL_SECS),
+            Duration::from_secs(WEBSOCKET_PROBE_INTERVAL_SECS),
+        );
+        
+        let shutdown = self.shutdown.clone();
+        loop {
+            tokio::select! {
+                _ = poll_timer.tick() => {
+                    debug!("Polling for messages");
+                    match self.try_process_messages().await {
+                        Ok(()) => {
+                            // Successful polls stand in for keep-alives while the WebSocket is down
+                            self.systemd.ready();
+                            self.set_state(ConnectionState::Polling);
                         }
                         Err(e) => {
-                            error!("WebSocket error: {}", e);
-                            break;
+                            error!("Failed to poll for messages: {}", e);
+                            self.record_error(format!("Failed to poll for messages: {}", e));
                         }
                     }
                 }
+                _ = probe_timer.tick() => {
+                    debug!("Checking whether the WebSocket is reachable again");
+                    match connect_websocket(&self.config).await {
+                        Ok(ws_stream) => return Some(ws_stream),
+                        Err(e) => debug!("WebSocket still unavailable: {}", e),
+                    }
+                }
+                _ = outbox_retry_due(&self.outbox) => {
+                    flush_outbox(&self.config, &self.state_dir, &mut self.outbox).await;
+                }
+                Some(reason) = self.wake_rx.recv() => {
+                    info!("Checking for messages and the WebSocket now: {}", reason);
+                    power::drain(&mut self.wake_rx);
+                    poll_timer.reset_immediately();
+                    probe_timer.reset_immediately();
+                }
+                Some(command) = self.commands.recv() => {
+                    if self.handle_command(command).await {
+                        info!("Checking for messages and the WebSocket now: reconnect requested");
+                        poll_timer.reset_immediately();
+                        probe_timer.reset_immediately();
+                    }
+                }
+                _ = shutdown.cancelled() => return None,
+            }
+        }
+    }
+}
+
+pub async fn consume_message_feed(
+    app_state: Arc<Mutex<AppState>>,
+    status_tx: watch::Sender<ConnectionStatus>,
+    events: mpsc::Sender<Event>,
+    delivered_tx: broadcast::Sender<Message>,
+    commands: mpsc::Receiver<FeedCommand>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let paths = app_paths();
+    let config = app_state.lock().await.config.clone();
+    
+    if config.credentials.secret.is_none() || config.credentials.device_id.is_none() {
+        panic!("Not logged in, login flow was disrupted");
+        // ! we should be logged in by now, so this is a bug
+    }
+    
+    // Pick up any actions left over from a previous run
+    let outbox = Outbox::load(&paths.state_dir).unwrap_or_else(|e| {
+        error!("Failed to load outbox, starting empty: {}", e);
+        Outbox::default()
+    });
+    
+    let hooks = load_hooks(&config);
+    let webhooks = start_webhooks(&config, &paths.state_dir);
+    let rules = load_rules(&paths.config_dir, &hooks, &webhooks);
+    let mqtt = start_mqtt(&config, &status_tx, &events);
+    let systemd = Notifier::from_env();
+    let watchdog = systemd.start_watchdog();
+    
+    let mut feed = Feed {
+        app_state,
+        config,
+        config_dir: paths.config_dir.clone(),
+        state_dir: paths.state_dir.clone(),
+        outbox,
+        transport: Transport::WebSocket,
+        keepalive_count: 0,
+        wake_rx: power::spawn_watchers(),
+        commands,
+        status: ConnectionStatus::default(),
+        status_tx,
+        events,
+        delivered_tx,
+        clock: Box::new(SystemClock),
+        rules,
+        hooks,
+        webhooks,
+        mqtt,
+        systemd,
+        _watchdog: watchdog,
+        shutdown: shutdown.clone(),
+    };
+    feed.publish_status();
+    
+    if !feed.outbox.is_empty() {
+        info!("Retrying {} pending outbox action(s)", feed.outbox.entries.len());
+        flush_outbox(&feed.config, &feed.state_dir, &mut feed.outbox).await;
+    }
+    
+    feed.process_messages().await;
+    
+    let mut websocket_failures = 0;
+    
+    while !shutdown.is_cancelled() {
+        // Make sure we have credentials
+        feed.sync_config().await;
+        if !feed.logged_in() {
+            error!("Missing credentials for WebSocket connection");
+            feed.set_state(ConnectionState::LoggedOut);
+            tokio::select! {
+                _ = time::sleep(Duration::from_millis(RECONNECT_DELAY_MS)) => {}
+                _ = shutdown.cancelled() => {}
+            }
+            continue;
+        }
+        
+        let connection = match feed.transport {
+            Transport::WebSocket => tokio::select! {
+                connection = connect_websocket(&feed.config) => connection,
+                _ = shutdown.cancelled() => break,
+            },
+            Transport::Polling => match feed.run_polling_session().await {
+                Some(ws_stream) => Ok(ws_stream),
+                None => break,
+            },
+        };
+        
+        match connection {
+            Ok(ws_stream) => {
+                info!("Connected to Pushover WebSocket");
+                websocket_failures = 0;
+                feed.set_state(ConnectionState::Connected);
+                if feed.transport == Transport::Polling {
+                    // Catch up on anything that arrived since the last poll
+                    feed.set_transport(Transport::WebSocket);
+                    feed.process_messages().await;
+                }
+                
+                let end = feed.run_websocket_session(ws_stream).await;
+                if let SessionEnd::Shutdown = end {
+                    break;
+                }
+                if feed.logged_in() {
+                    feed.set_state(ConnectionState::Reconnecting);
+                }
+                
+                if let SessionEnd::Woken = end {
+                    // Resync anything missed while asleep, then reconnect immediately
+                    power::drain(&mut feed.wake_rx);
+                    feed.process_messages().await;
+                    continue;
+                }
             }
             Err(e) => {
                 error!("Failed to connect to WebSocket: {}", e);
+                feed.record_error(format!("Failed to connect to WebSocket: {}", e));
+                feed.set_state(ConnectionState::Reconnecting);
+                websocket_failures += 1;
+                if websocket_failures >= feed.config.preferences.websocket_failures_before_polling {
+                    warn!("WebSocket failed {} times in a row, falling back to HTTP polling", websocket_failures);
+                    feed.set_transport(Transport::Polling);
+                    continue;
+                }
             }
         }
         
-        // Reconnect delay
+        // Reconnect delay, cut short if we just woke up or were asked to
         info!("Reconnecting in {} ms", RECONNECT_DELAY_MS);
-        time::sleep(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+        let delay = time::sleep(Duration::from_millis(RECONNECT_DELAY_MS));
+        tokio::pin!(delay);
+        loop {
+            tokio::select! {
+                _ = &mut delay => break,
+                Some(reason) = feed.wake_rx.recv() => {
+                    info!("Reconnecting now: {}", reason);
+                    power::drain(&mut feed.wake_rx);
+                    break;
+                }
+                Some(command) = feed.commands.recv() => {
+                    if feed.handle_command(command).await {
+                        info!("Reconnecting now: reconnect requested");
+                        break;
+                    }
+                }
+                _ = shutdown.cancelled() => break,
+            }
+        }
+        flush_outbox(&feed.config, &feed.state_dir, &mut feed.outbox).await;
     }
-}
\ No newline at end of file
+    
+    feed.shut_down().await;
+    Ok(())
+}
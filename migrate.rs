@@ -0,0 +1,290 @@
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde_json::{Map, Value};
+
+type Object = Map<String, Value>;
+
+// MIGRATIONS[n] upgrades a state.json (config.json in older versions) from schema version
+// n + 1 to n + 2. Files from before schema_version existed are version 1. Append a step
+// (never edit an old one) whenever the layout changes.
+const MIGRATIONS: &[fn(Object) -> Result<Object>] = &[v1_to_v2, v2_to_v3];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+// Bring the contents of a state.json up to the current schema, step by step
+pub fn upgrade(value: Value) -> Result<Value> {
+    let Value::Object(mut config) = value else {
+        return Err(anyhow!("Expected a JSON object"));
+    };
+
+    let version = match config.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| anyhow!("Invalid schema_version {}", version))? as u32,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Schema version {} is newer than this miniover supports ({})",
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        debug!("Upgrading config from schema version {} to {}", index + 1, index + 2);
+        config = migration(config)?;
+    }
+
+    config.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    Ok(Value::Object(config))
+}
+
+// Version 1 was flat: credentials, state and preferences side by side
+fn v1_to_v2(mut config: Object) -> Result<Object> {
+    const CREDENTIAL_KEYS: &[&str] = &["user_key", "secret", "device_id"];
+    const STATE_KEYS: &[&str] = &["last_message_id", "pause", "quiet_held"];
+
+    let mut credentials = Object::new();
+    for key in CREDENTIAL_KEYS {
+        if let Some(value) = config.remove(*key) {
+            credentials.insert(key.to_string(), value);
+        }
+    }
+
+    let mut state = Object::new();
+    for key in STATE_KEYS {
+        if let Some(value) = config.remove(*key) {
+            state.insert(key.to_string(), value);
+        }
+    }
+
+    // Everything else, including the credential store settings, was a preference
+    let preferences = std::mem::take(&mut config);
+
+    let mut upgraded = Object::new();
+    // Plaintext credentials are only passed through so secrets.rs can move them to the keyring
+    if !credentials.is_empty() {
+        upgraded.insert("credentials".to_string(), Value::Object(credentials));
+    }
+    upgraded.insert("preferences".to_string(), Value::Object(preferences));
+    upgraded.insert("state".to_string(), Value::Object(state));
+    Ok(upgraded)
+}
+
+// Version 2 kept the preferences here; they move to settings.toml when it's created (see
+// settings.rs), except start_on_boot which just mirrors the system and becomes state
+fn v2_to_v3(mut config: Object) -> Result<Object> {
+    let start_on_boot = match config.get_mut("preferences") {
+        Some(Value::Object(preferences)) => preferences.remove("start_on_boot"),
+        _ => None,
+    };
+
+    if let Some(start_on_boot) = start_on_boot {
+        match config.entry("state").or_insert_with(|| Value::Object(Object::new())) {
+            Value::Object(state) => {
+                state.insert("start_on_boot".to_string(), start_on_boot);
+            }
+            _ => return Err(anyhow!("Expected \"state\" to be an object")),
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quiet::QuietAction;
+    use crate::secrets::{CredentialStore, Credentials};
+    use crate::types::Config;
+    use crate::utils::load_config;
+    use chrono::{NaiveTime, Weekday};
+    use std::path::PathBuf;
+
+    // config.json from before schema_version: credentials, preferences and state side by side
+    const V1: &str = r#"{
+        "user_key": "u-123",
+        "secret": "s-456",
+        "device_id": "d-789",
+        "credentials": { "store": "file", "key_file": "/home/me/.config/miniover/credentials.key" },
+        "start_on_boot": true,
+        "last_message_id": "1041",
+        "poll_interval_secs": 120,
+        "websocket_failures_before_polling": 5,
+        "recent_messages": 20,
+        "pause": {
+            "started_at": 1700000000,
+            "until": 1700003600,
+            "held": [{ "id": 1040, "app": "Backup", "title": null, "priority": 0, "date": 1700000100 }]
+        },
+        "pause_bypass_emergency": false,
+        "quiet_hours": {
+            "periods": [{ "days": ["Mon", "Fri"], "start": "22:00:00", "end": "07:00:00" }],
+            "min_priority": 1,
+            "action": "hold"
+        },
+        "quiet_held": [],
+        "hooks": [],
+        "webhooks": [],
+        "mqtt": null
+    }"#;
+
+    // Credentials in the keyring, preferences and state split
+    const V2: &str = r#"{
+        "schema_version": 2,
+        "preferences": {
+            "credentials": { "store": "keyring", "key_file": null },
+            "start_on_boot": true,
+            "poll_interval_secs": 90,
+            "recent_messages": 5,
+            "quiet_hours": { "periods": [], "min_priority": 2, "action": "silent" }
+        },
+        "state": {
+            "last_message_id": "2001",
+            "pause": null,
+            "quiet_held": [{
+                "id": 2000, "id_str": "2000", "message": "Disk full", "app": "Backup", "aid": 7,
+                "aid_str": "7", "icon": "", "date": 1700000000, "priority": 0, "acked": 0, "umid": 2000,
+                "umid_str": "2000", "title": null, "url": null, "url_title": null, "sound": null,
+                "html": null, "receipt": null
+            }]
+        }
+    }"#;
+
+    // Only state, preferences live in settings.toml
+    const V3: &str = r#"{
+        "schema_version": 3,
+        "state": {
+            "start_on_boot": false,
+            "last_message_id": "3001",
+            "pause": { "started_at": 1700000000, "until": null, "held": [] },
+            "quiet_held": []
+        }
+    }"#;
+
+    fn load(json: &str) -> Config {
+        let upgraded = upgrade(serde_json::from_str(json).unwrap()).unwrap();
+        serde_json::from_value(upgraded).unwrap()
+    }
+
+    #[test]
+    fn upgrades_flat_v1() {
+        let config = load(V1);
+
+        assert_eq!(config.schema_version, SCHEMA_VERSION);
+        assert_eq!(
+            config.credentials,
+            Credentials {
+                user_key: Some("u-123".to_string()),
+                secret: Some("s-456".to_string()),
+                device_id: Some("d-789".to_string()),
+            }
+        );
+
+        let preferences = config.legacy_preferences.expect("preferences carried over for settings.toml");
+        assert_eq!(preferences.credentials.store, CredentialStore::File);
+        assert_eq!(preferences.credentials.key_file, Some(PathBuf::from("/home/me/.config/miniover/credentials.key")));
+        assert_eq!(preferences.poll_interval_secs, 120);
+        assert_eq!(preferences.websocket_failures_before_polling, 5);
+        assert_eq!(preferences.recent_messages, 20);
+        assert!(!preferences.pause_bypass_emergency);
+        assert_eq!(preferences.quiet_hours.min_priority, 1);
+        assert_eq!(preferences.quiet_hours.action, QuietAction::Hold);
+        let period = &preferences.quiet_hours.periods[0];
+        assert_eq!(period.days, [Weekday::Mon, Weekday::Fri]);
+        assert_eq!(period.start, NaiveTime::from_hms_opt(22, 0, 0).unwrap());
+        assert_eq!(period.end, NaiveTime::from_hms_opt(7, 0, 0).unwrap());
+        assert!(preferences.hooks.is_empty() && preferences.webhooks.is_empty() && preferences.mqtt.is_none());
+
+        assert!(config.state.start_on_boot);
+        assert_eq!(config.state.last_message_id.as_deref(), Some("1041"));
+        let pause = config.state.pause.expect("pause kept");
+        assert_eq!((pause.started_at, pause.until), (1700000000, Some(1700003600)));
+        assert_eq!(pause.held.len(), 1);
+        assert_eq!((pause.held[0].id, pause.held[0].app.as_str()), (1040, "Backup"));
+        assert!(config.state.quiet_held.is_empty());
+    }
+
+    #[test]
+    fn v1_keys_leave_the_top_level() {
+        let upgraded = upgrade(serde_json::from_str(V1).unwrap()).unwrap();
+        let keys: Vec<&str> = upgraded.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, ["credentials", "preferences", "schema_version", "state"]);
+        assert!(upgraded["preferences"].get("start_on_boot").is_none());
+        assert!(upgraded["preferences"].get("user_key").is_none());
+    }
+
+    #[test]
+    fn upgrades_v2() {
+        let config = load(V2);
+
+        assert_eq!(config.schema_version, SCHEMA_VERSION);
+        assert_eq!(config.credentials, Credentials::default());
+
+        let preferences = config.legacy_preferences.expect("preferences carried over for settings.toml");
+        assert_eq!(preferences.credentials.store, CredentialStore::Keyring);
+        assert_eq!(preferences.credentials.key_file, None);
+        assert_eq!(preferences.poll_interval_secs, 90);
+        assert_eq!(preferences.recent_messages, 5);
+        // Missing from the file, so the default
+        assert_eq!(preferences.websocket_failures_before_polling, 3);
+        assert!(preferences.quiet_hours.periods.is_empty());
+        assert_eq!(preferences.quiet_hours.action, QuietAction::Silent);
+
+        // start_on_boot moved from the preferences to the state
+        assert!(config.state.start_on_boot);
+        assert_eq!(config.state.last_message_id.as_deref(), Some("2001"));
+        assert!(config.state.pause.is_none());
+        assert_eq!(config.state.quiet_held.len(), 1);
+        let held = &config.state.quiet_held[0];
+        assert_eq!((held.id, held.app.as_str(), held.message.as_str(), held.aid), (2000, "Backup", "Disk full", 7));
+    }
+
+    #[test]
+    fn passes_current_v3_through_unchanged() {
+        let original: Value = serde_json::from_str(V3).unwrap();
+        assert_eq!(upgrade(original.clone()).unwrap(), original);
+
+        let config = load(V3);
+        assert_eq!(config.schema_version, 3);
+        assert_eq!(config.credentials, Credentials::default());
+        assert!(config.legacy_preferences.is_none());
+        assert!(!config.state.start_on_boot);
+        assert_eq!(config.state.last_message_id.as_deref(), Some("3001"));
+        let pause = config.state.pause.as_ref().expect("pause kept");
+        assert_eq!((pause.started_at, pause.until, pause.held.len()), (1700000000, None, 0));
+        assert!(config.state.quiet_held.is_empty());
+
+        // And is written back the same way
+        assert_eq!(serde_json::to_value(&config).unwrap(), original);
+    }
+
+    #[test]
+    fn rejects_future_and_invalid_versions() {
+        let cases = [
+            (r#"{"schema_version": 4, "state": {}}"#, "Schema version 4 is newer than this miniover supports (3)"),
+            (r#"{"schema_version": 0}"#, "Invalid schema_version 0"),
+            (r#"{"schema_version": "3"}"#, "Invalid schema_version \"3\""),
+            (r#"[]"#, "Expected a JSON object"),
+        ];
+
+        for (json, expected) in cases {
+            let error = upgrade(serde_json::from_str(json).unwrap()).expect_err(json);
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn loading_a_future_file_fails_without_touching_it() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let path = state_dir.path().join("state.json");
+        let future = r#"{"schema_version": 99, "state": {"something_new": true}}"#;
+        std::fs::write(&path, future).unwrap();
+
+        let error = format!("{:#}", load_config(state_dir.path()).unwrap_err());
+        assert!(error.starts_with("Failed to load"), "{}", error);
+        assert!(error.ends_with("Schema version 99 is newer than this miniover supports (3)"), "{}", error);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), future);
+    }
+}
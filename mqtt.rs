@@ -0,0 +1,540 @@
+use crate::pause::PauseDuration;
+use crate::types::{ConnectionStatus, Event, Message};
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Publishes queued while the broker is unreachable, newer ones are dropped beyond this
+const REQUEST_QUEUE_SIZE: usize = 64;
+// How long the offline state and disconnect get to reach the broker when quitting
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Bridge to an MQTT broker (plain TCP, e.g. a local Mosquitto)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    // Every message is published here; {app} and {priority} are filled in
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    // Retained copy of the newest message
+    #[serde(default = "default_last_message_topic")]
+    pub last_message_topic: String,
+    // Retained connection state, set to offline by the broker if we vanish
+    #[serde(default = "default_state_topic")]
+    pub state_topic: String,
+    // Commands like "pause 1h" or "ack <receipt>" are taken from here when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_topic: Option<String>,
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "miniover".to_string()
+}
+
+fn default_topic() -> String {
+    "miniover/message/{app}/{priority}".to_string()
+}
+
+fn default_last_message_topic() -> String {
+    "miniover/last_message".to_string()
+}
+
+fn default_state_topic() -> String {
+    "miniover/state".to_string()
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+// Payload of the state topic
+#[derive(Serialize)]
+struct StatePayload<'a> {
+    online: bool,
+    #[serde(flatten)]
+    status: Option<&'a ConnectionStatus>,
+}
+
+// Handle used by the message feed to publish messages
+#[derive(Default)]
+pub struct Mqtt {
+    bridge: Option<Bridge>,
+}
+
+struct Bridge {
+    client: AsyncClient,
+    config: MqttConfig,
+    qos: QoS,
+    task: JoinHandle<()>,
+}
+
+impl Mqtt {
+    // Validate the config and start the connection; does nothing without one
+    pub fn start(
+        config: Option<&MqttConfig>,
+        status_rx: watch::Receiver<ConnectionStatus>,
+        events: mpsc::Sender<Event>,
+    ) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let qos = validate(config)?;
+
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        options.set_last_will(LastWill::new(
+            &config.state_topic,
+            state_payload(None),
+            qos,
+            true,
+        ));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+
+        let (client, eventloop) = AsyncClient::new(options, REQUEST_QUEUE_SIZE);
+        info!("Bridging messages to MQTT broker {}:{}", config.host, config.port);
+        let task = tokio::spawn(run(config.clone(), qos, client.clone(), eventloop, status_rx, events));
+
+        Ok(Self {
+            bridge: Some(Bridge {
+                client,
+                config: config.clone(),
+                qos,
+                task,
+            }),
+        })
+    }
+
+    // Reconnect with an edited config, if it changed; an invalid one leaves the bridge alone
+    pub async fn reconfigure(
+        &mut self,
+        config: Option<&MqttConfig>,
+        status_rx: watch::Receiver<ConnectionStatus>,
+        events: mpsc::Sender<Event>,
+    ) -> Result<()> {
+        if self.bridge.as_ref().map(|bridge| &bridge.config) == config {
+            return Ok(());
+        }
+        if let Some(config) = config {
+            validate(config)?;
+        }
+
+        std::mem::take(self).shutdown().await;
+        *self = Self::start(config, status_rx, events)?;
+        Ok(())
+    }
+
+    // Mark us offline and disconnect; a clean disconnect doesn't trigger the last will
+    pub async fn shutdown(self) {
+        let Some(mut bridge) = self.bridge else {
+            return;
+        };
+
+        if let Err(e) = bridge.client.try_publish(&bridge.config.state_topic, bridge.qos, true, state_payload(None)) {
+            warn!("Failed to publish MQTT offline state: {}", e);
+        }
+        if let Err(e) = bridge.client.try_disconnect() {
+            warn!("Failed to disconnect from MQTT broker: {}", e);
+        }
+        if time::timeout(SHUTDOWN_TIMEOUT, &mut bridge.task).await.is_err() {
+            debug!("MQTT broker didn't acknowledge the disconnect in time");
+            bridge.task.abort();
+        }
+    }
+
+    // Publish to the templated topic and the retained last message topic
+    pub fn publish(&self, message: &Message) {
+        let Some(Bridge { client, config, qos, .. }) = &self.bridge else {
+            return;
+        };
+
+        let payload = match serde_json::to_vec(message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to render message {} for MQTT: {}", message.id, e);
+                return;
+            }
+        };
+
+        let topic = render_topic(&config.topic, message);
+        for (topic, retain) in [(topic.as_str(), false), (config.last_message_topic.as_str(), true)] {
+            // Never block the feed on the broker; the queue is only full while it's unreachable
+            if let Err(e) = client.try_publish(topic, *qos, retain, payload.clone()) {
+                warn!("Failed to publish message {} to MQTT topic {}: {}", message.id, topic, e);
+            }
+        }
+    }
+}
+
+pub fn validate(config: &MqttConfig) -> Result<QoS> {
+    if config.host.is_empty() {
+        return Err(anyhow!("MQTT host is empty"));
+    }
+    if config.client_id.is_empty() {
+        return Err(anyhow!("MQTT client_id is empty"));
+    }
+    for (name, topic) in [
+        ("topic", &config.topic),
+        ("last_message_topic", &config.last_message_topic),
+        ("state_topic", &config.state_topic),
+    ] {
+        if topic.is_empty() || !rumqttc::valid_topic(topic) {
+            return Err(anyhow!("Invalid MQTT {} '{}'", name, topic));
+        }
+    }
+    if let Some(topic) = &config.command_topic
+        && (topic.is_empty() || !rumqttc::valid_filter(topic))
+    {
+        return Err(anyhow!("Invalid MQTT command_topic '{}'", topic));
+    }
+    rumqttc::qos(config.qos).map_err(|_| anyhow!("MQTT qos must be 0, 1 or 2"))
+}
+
+// Topic levels can't contain separators or wildcards, so those are replaced in app names
+fn render_topic(template: &str, message: &Message) -> String {
+    let app: String = match message.app.is_empty() {
+        true => "unknown".to_string(),
+        false => message
+            .app
+            .chars()
+            .map(|c| if matches!(c, '/' | '+' | '#') { '_' } else { c })
+            .collect(),
+    };
+
+    template
+        .replace("{app}", &app)
+        .replace("{priority}", &message.priority.to_string())
+}
+
+fn state_payload(status: Option<&ConnectionStatus>) -> Vec<u8> {
+    let payload = StatePayload {
+        online: status.is_some(),
+        status,
+    };
+    serde_json::to_vec(&payload).unwrap_or_default()
+}
+
+// Drive the connection: keep the state topic current and turn commands into events
+async fn run(
+    config: MqttConfig,
+    qos: QoS,
+    client: AsyncClient,
+    mut eventloop: EventLoop,
+    mut status_rx: watch::Receiver<ConnectionStatus>,
+    events: mpsc::Sender<Event>,
+) {
+    let publish_state = |status: &ConnectionStatus| {
+        if let Err(e) = client.try_publish(&config.state_topic, qos, true, state_payload(Some(status))) {
+            warn!("Failed to publish MQTT state: {}", e);
+        }
+    };
+
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker");
+                    // Subscriptions don't survive a reconnect with a clean session
+                    if let Some(topic) = &config.command_topic
+                        && let Err(e) = client.try_subscribe(topic, qos)
+                    {
+                        error!("Failed to subscribe to MQTT command topic: {}", e);
+                    }
+                    publish_state(&status_rx.borrow());
+                }
+                Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => {
+                    let text = String::from_utf8_lossy(&publish.payload);
+                    match parse_command(&text) {
+                        Ok(event) => {
+                            info!("MQTT command from {}: {}", publish.topic, text.trim());
+                            if events.send(event).await.is_err() {
+                                error!("Event channel closed, stopping MQTT bridge");
+                                return;
+                            }
+                        }
+                        Err(e) => warn!("Ignoring MQTT command '{}': {}", text.trim(), e),
+                    }
+                }
+                Ok(rumqttc::Event::Outgoing(Outgoing::Disconnect)) => {
+                    info!("Disconnected from MQTT broker");
+                    return;
+                }
+                Ok(event) => debug!("MQTT event: {:?}", event),
+                Err(e) => {
+                    // Polling again reconnects
+                    warn!("MQTT connection error, retrying in {}s: {}", RECONNECT_DELAY.as_secs(), e);
+                    time::sleep(RECONNECT_DELAY).await;
+                }
+            },
+            Ok(()) = status_rx.changed() => {
+                publish_state(&status_rx.borrow_and_update());
+            }
+        }
+    }
+}
+
+// "pause [30m|1h|tomorrow|indefinitely]", "resume", "ack <receipt>" or "reconnect"
+fn parse_command(text: &str) -> Result<Event> {
+    let mut words = text.split_whitespace();
+    let command = words.next().unwrap_or_default().to_ascii_lowercase();
+    let argument = words.next();
+    if words.next().is_some() {
+        return Err(anyhow!("Too many arguments"));
+    }
+
+    match (command.as_str(), argument) {
+        ("pause", None) => Ok(Event::Pause(PauseDuration::Indefinitely)),
+        ("pause", Some(duration)) => PauseDuration::parse(duration)
+            .map(Event::Pause)
+            .ok_or_else(|| anyhow!("Unknown pause duration '{}'", duration)),
+        ("resume", None) => Ok(Event::Resume),
+        ("ack" | "acknowledge", Some(receipt)) => Ok(Event::Acknowledge(receipt.to_string())),
+        ("reconnect", None) => Ok(Event::Reconnect),
+        ("", _) => Err(anyhow!("Empty command")),
+        _ => Err(anyhow!("Unknown command")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::find_program;
+    use serde_json::Value;
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+
+    const WAIT: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn renders_topics() {
+        let cases = [
+            ("miniover/message/{app}/{priority}", "Backups", 1, "miniover/message/Backups/1"),
+            ("miniover/message/{app}/{priority}", "", -2, "miniover/message/unknown/-2"),
+            ("miniover/message/{app}/{priority}", "a/b+c#d", 0, "miniover/message/a_b_c_d/0"),
+            ("{priority}/{app}/{app}", "Home Assistant", 2, "2/Home Assistant/Home Assistant"),
+            ("miniover/everything", "Backups", 1, "miniover/everything"),
+        ];
+
+        for (template, app, priority, expected) in cases {
+            let message = Message {
+                app: app.to_string(),
+                priority,
+                ..Default::default()
+            };
+            let topic = render_topic(template, &message);
+            assert_eq!(topic, expected, "{} with app '{}'", template, app);
+            assert!(rumqttc::valid_topic(&topic), "{}", topic);
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        let cases = [
+            ("pause", "Pause(Indefinitely)"),
+            ("pause 30m", "Pause(ThirtyMinutes)"),
+            ("PAUSE 1H", "Pause(OneHour)"),
+            ("pause tomorrow", "Pause(UntilTomorrow)"),
+            ("pause forever", "Pause(Indefinitely)"),
+            ("  resume\n", "Resume"),
+            ("ack r1234", "Acknowledge(\"r1234\")"),
+            ("acknowledge R1234", "Acknowledge(\"R1234\")"),
+            ("reconnect", "Reconnect"),
+        ];
+        for (text, expected) in cases {
+            let event = parse_command(text).unwrap_or_else(|e| panic!("'{}': {}", text, e));
+            assert_eq!(format!("{:?}", event), expected, "'{}'", text);
+        }
+
+        let errors = [
+            ("", "Empty command"),
+            ("   ", "Empty command"),
+            ("pause 2h", "Unknown pause duration '2h'"),
+            ("pause 1h now", "Too many arguments"),
+            ("resume now", "Unknown command"),
+            ("ack", "Unknown command"),
+            ("quit", "Unknown command"),
+        ];
+        for (text, expected) in errors {
+            let error = parse_command(text).expect_err(text);
+            assert_eq!(error.to_string(), expected, "'{}'", text);
+        }
+    }
+
+    #[test]
+    fn state_payload_reports_the_connection() {
+        let offline: Value = serde_json::from_slice(&state_payload(None)).unwrap();
+        assert_eq!(offline, serde_json::json!({"online": false}));
+
+        let status = ConnectionStatus {
+            last_connected: Some(1700000000),
+            ..Default::default()
+        };
+        let online: Value = serde_json::from_slice(&state_payload(Some(&status))).unwrap();
+        assert_eq!(online["online"], true);
+        assert_eq!(online["last_connected"], 1700000000);
+    }
+
+    // A mosquitto of our own on a free port, stopped when dropped
+    struct Broker {
+        daemon: Child,
+        port: u16,
+    }
+
+    impl Broker {
+        async fn start(program: &Path) -> Self {
+            let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+            let daemon = Command::new(program)
+                .args(["-p", &port.to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("mosquitto starts");
+            let broker = Self { daemon, port };
+
+            time::timeout(WAIT, async {
+                while tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .expect("mosquitto listening");
+            broker
+        }
+    }
+
+    impl Drop for Broker {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    // Against MINIOVER_TEST_MQTT (host:port of a broker without authentication) when set,
+    // otherwise a mosquitto started for the test; skipped without either
+    #[tokio::test]
+    async fn bridges_to_a_local_broker() {
+        let (host, port, _broker) = match std::env::var("MINIOVER_TEST_MQTT") {
+            Ok(address) => {
+                let (host, port) = address.rsplit_once(':').expect("MINIOVER_TEST_MQTT is host:port");
+                (host.to_string(), port.parse().unwrap(), None)
+            }
+            Err(_) => {
+                let Some(program) = find_program("mosquitto") else {
+                    eprintln!("mosquitto isn't installed and MINIOVER_TEST_MQTT isn't set, skipping");
+                    return;
+                };
+                let broker = Broker::start(&program).await;
+                ("127.0.0.1".to_string(), broker.port, Some(broker))
+            }
+        };
+        let host = host.as_str();
+        let prefix = format!("miniover-test/{}", std::process::id());
+
+        // Watches everything below the prefix
+        let mut options = MqttOptions::new(format!("miniover-test-{}", std::process::id()), host, port);
+        options.set_keep_alive(KEEP_ALIVE);
+        let (observer, mut observer_loop) = AsyncClient::new(options, REQUEST_QUEUE_SIZE);
+        let (packets_tx, mut packets) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok(event) = observer_loop.poll().await {
+                if let rumqttc::Event::Incoming(packet) = event {
+                    packets_tx.send(packet).ok();
+                }
+            }
+        });
+        observer.subscribe(format!("{}/#", prefix), QoS::AtLeastOnce).await.unwrap();
+        time::timeout(WAIT, async {
+            while !matches!(packets.recv().await, Some(Packet::SubAck(_))) {}
+        })
+        .await
+        .expect("observer subscribed");
+
+        // Payloads published to `topic`, skipping anything else
+        let mut next_on = async |topic: String| -> Value {
+            time::timeout(WAIT, async {
+                loop {
+                    if let Some(Packet::Publish(publish)) = packets.recv().await
+                        && publish.topic == topic
+                    {
+                        return serde_json::from_slice(&publish.payload).unwrap();
+                    }
+                }
+            })
+            .await
+            .unwrap_or_else(|_| panic!("nothing published to {}", topic))
+        };
+
+        let config = MqttConfig {
+            host: host.to_string(),
+            port,
+            client_id: format!("miniover-test-bridge-{}", std::process::id()),
+            username: None,
+            password: None,
+            topic: format!("{}/message/{{app}}/{{priority}}", prefix),
+            last_message_topic: format!("{}/last_message", prefix),
+            state_topic: format!("{}/state", prefix),
+            command_topic: Some(format!("{}/command", prefix)),
+            qos: 1,
+        };
+        let (_status_tx, status_rx) = watch::channel(ConnectionStatus::default());
+        let (events_tx, mut events) = mpsc::channel(8);
+        let mut mqtt = Mqtt::start(Some(&config), status_rx, events_tx).unwrap();
+
+        let state = next_on(config.state_topic.clone()).await;
+        assert_eq!(state["online"], true);
+
+        let message = Message {
+            id: 7,
+            app: "Backups".to_string(),
+            priority: 1,
+            message: "Nightly backup failed".to_string(),
+            ..Default::default()
+        };
+        mqtt.publish(&message);
+        let published = next_on(format!("{}/message/Backups/1", prefix)).await;
+        assert_eq!(published["id"], 7);
+        assert_eq!(published["message"], "Nightly backup failed");
+        let last = next_on(config.last_message_topic.clone()).await;
+        assert_eq!(last["id"], 7);
+
+        observer.publish(format!("{}/command", prefix), QoS::AtLeastOnce, false, "pause 1h").await.unwrap();
+        let event = time::timeout(WAIT, events.recv()).await.expect("command arrived").unwrap();
+        assert!(matches!(event, Event::Pause(PauseDuration::OneHour)), "{:?}", event);
+
+        // Vanishing without a disconnect makes the broker publish the last will
+        let bridge = mqtt.bridge.take().unwrap();
+        bridge.task.abort();
+        drop(bridge);
+        let will = next_on(config.state_topic.clone()).await;
+        assert_eq!(will, serde_json::json!({"online": false}));
+
+        // Clear the retained messages again
+        for topic in [&config.state_topic, &config.last_message_topic] {
+            observer.publish(topic.as_str(), QoS::AtLeastOnce, true, Vec::new()).await.unwrap();
+        }
+        observer.disconnect().await.ok();
+    }
+}
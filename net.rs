@@ -0,0 +1,272 @@
+use crate::tls;
+use crate::types::{Config, ProxyConfig};
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::{debug, info};
+use reqwest::{Client, NoProxy, Proxy, Url};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+use tokio_tungstenite::Connector;
+
+const PROXY_ENV_VARS: [&str; 4] = ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"];
+
+// Proxy and HTTP client shared by the REST calls and the WebSocket tunnel
+static HTTP_CLIENT: RwLock<Option<Client>> = RwLock::new(None);
+static WS_PROXY: RwLock<Option<ResolvedProxy>> = RwLock::new(None);
+static WS_CONNECTOR: RwLock<Option<Connector>> = RwLock::new(None);
+static ENDPOINTS: RwLock<Option<EndpointsConfig>> = RwLock::new(None);
+
+// Where the Open Client API lives; only worth changing to test against a local server
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointsConfig {
+    pub api_url: String,
+    pub websocket_url: String,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "https://api.pushover.net/1".to_string(),
+            websocket_url: "wss://client.pushover.net/push".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ResolvedProxy {
+    url: Url,
+    // Proxies taken from the environment honour NO_PROXY
+    from_env: bool,
+}
+
+// Apply the endpoint, proxy and TLS settings from the config; must run before any request is made
+pub fn configure(config: &Config) -> Result<()> {
+    let endpoints = check_endpoints(&config.preferences.endpoints)?;
+    let proxy = resolve_proxy(&config.preferences.proxy)?;
+
+    let mut builder = Client::builder();
+    match &proxy {
+        Some(proxy) => {
+            info!("Using proxy {}", redact(&proxy.url));
+            let mut reqwest_proxy = Proxy::all(proxy.url.clone())?;
+            if proxy.from_env {
+                reqwest_proxy = reqwest_proxy.no_proxy(NoProxy::from_env());
+            }
+            builder = builder.proxy(reqwest_proxy);
+        }
+        None => {
+            debug!("No proxy configured");
+            builder = builder.no_proxy();
+        }
+    }
+
+    let (builder, connector) = tls::configure(builder, &config.preferences.tls)?;
+
+    *HTTP_CLIENT.write().unwrap() = Some(builder.build()?);
+    *WS_PROXY.write().unwrap() = proxy;
+    *WS_CONNECTOR.write().unwrap() = Some(connector);
+    *ENDPOINTS.write().unwrap() = Some(endpoints);
+
+    Ok(())
+}
+
+fn check_endpoints(endpoints: &EndpointsConfig) -> Result<EndpointsConfig> {
+    let api_url = Url::parse(&endpoints.api_url).map_err(|e| anyhow!("Invalid endpoints.api_url: {}", e))?;
+    if !matches!(api_url.scheme(), "https" | "http") {
+        return Err(anyhow!("endpoints.api_url must be an https:// or http:// URL"));
+    }
+
+    let websocket_url = Url::parse(&endpoints.websocket_url).map_err(|e| anyhow!("Invalid endpoints.websocket_url: {}", e))?;
+    if !matches!(websocket_url.scheme(), "wss" | "ws") || websocket_url.host_str().is_none() {
+        return Err(anyhow!("endpoints.websocket_url must be a wss:// or ws:// URL"));
+    }
+
+    if endpoints != &EndpointsConfig::default() {
+        info!("Using Pushover endpoints {} and {}", endpoints.api_url, endpoints.websocket_url);
+    }
+    Ok(EndpointsConfig {
+        // Paths are appended with a slash of their own
+        api_url: endpoints.api_url.trim_end_matches('/').to_string(),
+        websocket_url: endpoints.websocket_url.clone(),
+    })
+}
+
+// Open Client API base URL, without a trailing slash
+pub fn api_url() -> String {
+    ENDPOINTS.read().unwrap().clone().unwrap_or_default().api_url
+}
+
+pub fn websocket_url() -> String {
+    ENDPOINTS.read().unwrap().clone().unwrap_or_default().websocket_url
+}
+
+// HTTP client for Open Client REST calls
+pub fn http_client() -> Client {
+    HTTP_CLIENT.read().unwrap().clone().unwrap_or_default()
+}
+
+// TLS connector for the WebSocket, matching the REST client's trust settings
+pub fn ws_connector() -> Option<Connector> {
+    WS_CONNECTOR.read().unwrap().clone()
+}
+
+// Open a TCP stream to host:port, tunnelling through the configured proxy if any
+pub async fn connect_tcp(host: &str, port: u16) -> Result<TcpStream> {
+    let proxy = WS_PROXY.read().unwrap().clone();
+
+    let proxy = match proxy {
+        Some(proxy) if !(proxy.from_env && no_proxy_matches(host)) => proxy,
+        _ => return Ok(TcpStream::connect((host, port)).await?),
+    };
+
+    debug!("Connecting to {}:{} via proxy {}", host, port, redact(&proxy.url));
+    match proxy.url.scheme() {
+        "http" => connect_http_tunnel(&proxy.url, host, port).await,
+        "socks5" => {
+            // socks5:// resolves the target locally, socks5h:// leaves it to the proxy
+            let target = tokio::net::lookup_host((host, port))
+                .await?
+                .next()
+                .ok_or_else(|| anyhow!("Could not resolve {}", host))?;
+            connect_socks5(&proxy.url, target.to_string()).await
+        }
+        "socks5h" => connect_socks5(&proxy.url, format!("{}:{}", host, port)).await,
+        scheme => Err(anyhow!("Unsupported proxy scheme: {}", scheme)),
+    }
+}
+
+fn resolve_proxy(config: &ProxyConfig) -> Result<Option<ResolvedProxy>> {
+    if let Some(url) = config.url.as_deref().filter(|url| !url.is_empty()) {
+        return Ok(Some(ResolvedProxy {
+            url: parse_proxy_url(url)?,
+            from_env: false,
+        }));
+    }
+
+    if !config.from_env {
+        return Ok(None);
+    }
+
+    for var in PROXY_ENV_VARS {
+        if let Ok(url) = std::env::var(var) && !url.is_empty() {
+            debug!("Using proxy from {}", var);
+            return Ok(Some(ResolvedProxy {
+                url: parse_proxy_url(&url)?,
+                from_env: true,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+fn parse_proxy_url(url: &str) -> Result<Url> {
+    // Bare host:port is treated as an HTTP proxy, like curl does
+    let url = if url.contains("://") {
+        Url::parse(url)?
+    } else {
+        Url::parse(&format!("http://{}", url))?
+    };
+
+    match url.scheme() {
+        "http" | "socks5" | "socks5h" => {}
+        scheme => return Err(anyhow!("Unsupported proxy scheme '{}', use http, socks5 or socks5h", scheme)),
+    }
+
+    if url.host_str().is_none() {
+        return Err(anyhow!("Proxy URL has no host: {}", redact(&url)));
+    }
+
+    Ok(url)
+}
+
+// Proxy URL without credentials, for logging
+fn redact(url: &Url) -> String {
+    let mut url = url.clone();
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    url.to_string()
+}
+
+fn proxy_addr(url: &Url) -> Result<(String, u16)> {
+    let host = url.host_str().ok_or_else(|| anyhow!("Proxy URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(match url.scheme() {
+        "http" => 8080,
+        _ => 1080,
+    });
+    Ok((host.to_string(), port))
+}
+
+fn proxy_credentials(url: &Url) -> Option<(String, String)> {
+    if url.username().is_empty() {
+        return None;
+    }
+    Some((url.username().to_string(), url.password().unwrap_or_default().to_string()))
+}
+
+// HTTP CONNECT handshake, leaving the stream ready for TLS
+async fn connect_http_tunnel(proxy: &Url, host: &str, port: u16) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy_addr(proxy)?).await?;
+
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if let Some((username, password)) = proxy_credentials(proxy) {
+        let token = BASE64.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response head byte-wise so nothing past it is consumed
+    let mut reader = BufReader::with_capacity(1, &mut stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(anyhow!("Proxy CONNECT failed: {}", status_line.trim()));
+    }
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("Proxy closed the connection during CONNECT"));
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+
+    Ok(stream)
+}
+
+async fn connect_socks5(proxy: &Url, target: String) -> Result<TcpStream> {
+    let proxy_addr = proxy_addr(proxy)?;
+    let proxy_addr = (proxy_addr.0.as_str(), proxy_addr.1);
+
+    let stream = match proxy_credentials(proxy) {
+        Some((username, password)) => {
+            Socks5Stream::connect_with_password(proxy_addr, target.as_str(), &username, &password).await?
+        }
+        None => Socks5Stream::connect(proxy_addr, target.as_str()).await?,
+    };
+
+    Ok(stream.into_inner())
+}
+
+// Minimal NO_PROXY matching: exact hosts, domain suffixes and '*'
+fn no_proxy_matches(host: &str) -> bool {
+    let no_proxy = std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .unwrap_or_default();
+
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
@@ -0,0 +1,185 @@
+use crate::messages::{acknowledge_emergency, delete_messages};
+use crate::types::Config;
+use crate::utils::{unix_now, write_atomic};
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use std::time::Duration;
+
+pub const OUTBOX_FILENAME: &str = "outbox.json";
+const RETRY_BASE_DELAY_SECS: i64 = 5;
+const RETRY_MAX_DELAY_SECS: i64 = 15 * 60;
+
+// Server-side actions that must eventually reach Pushover
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PendingAction {
+    Acknowledge { receipt: String },
+    UpdateHighestMessage { message_id: String },
+}
+
+impl std::fmt::Display for PendingAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PendingAction::Acknowledge { receipt } => write!(f, "acknowledge receipt {}", receipt),
+            PendingAction::UpdateHighestMessage { message_id } => write!(f, "update highest message to {}", message_id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub action: PendingAction,
+    pub queued_at: i64,
+    pub attempts: u32,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+}
+
+// Persistent queue of pending server-side actions, retried with backoff
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Outbox {
+    pub entries: Vec<OutboxEntry>,
+}
+
+impl Outbox {
+    pub fn load(state_dir: &Path) -> Result<Outbox> {
+        let outbox_path = state_dir.join(OUTBOX_FILENAME);
+
+        if !outbox_path.exists() {
+            return Ok(Outbox::default());
+        }
+
+        let outbox_data = fs::read_to_string(outbox_path)?;
+        let outbox: Outbox = serde_json::from_str(&outbox_data)?;
+
+        Ok(outbox)
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        let outbox_path = state_dir.join(OUTBOX_FILENAME);
+        let outbox_json = serde_json::to_string_pretty(self)?;
+
+        write_atomic(&outbox_path, outbox_json.as_bytes())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Queue an action, collapsing duplicates so the outbox can't grow unbounded
+    pub fn push(&mut self, action: PendingAction) {
+        match &action {
+            PendingAction::Acknowledge { .. } => {
+                if self.entries.iter().any(|entry| entry.action == action) {
+                    return;
+                }
+            }
+            PendingAction::UpdateHighestMessage { message_id } => {
+                // Only the highest message ID matters, older updates are superseded
+                let new_id = message_id.parse::<i64>().unwrap_or(0);
+                let has_newer = self.entries.iter().any(|entry| matches!(
+                    &entry.action,
+                    PendingAction::UpdateHighestMessage { message_id } if message_id.parse::<i64>().unwrap_or(0) >= new_id
+                ));
+                if has_newer {
+                    return;
+                }
+
+                self.entries.retain(|entry| !matches!(entry.action, PendingAction::UpdateHighestMessage { .. }));
+            }
+        }
+
+        let now = unix_now();
+        self.entries.push(OutboxEntry {
+            action,
+            queued_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+        });
+    }
+
+    // Time until the next entry is due for a retry, if any
+    pub fn next_due(&self) -> Option<Duration> {
+        let now = unix_now();
+        self.entries
+            .iter()
+            .map(|entry| entry.next_attempt_at)
+            .min()
+            .map(|at| Duration::from_secs((at - now).max(0) as u64))
+    }
+
+    // Make every entry due now regardless of backoff, e.g. for a last try before quitting
+    pub fn retry_all_now(&mut self) {
+        let now = unix_now();
+        for entry in &mut self.entries {
+            entry.next_attempt_at = entry.next_attempt_at.min(now);
+        }
+    }
+
+    // Attempt every due entry once; returns true if the outbox changed
+    pub async fn flush(&mut self, config: &Config) -> bool {
+        let (secret, device_id) = match (&config.credentials.secret, &config.credentials.device_id) {
+            (Some(secret), Some(device_id)) => (secret, device_id),
+            _ => {
+                warn!("Not flushing outbox, missing credentials");
+                return false;
+            }
+        };
+
+        let now = unix_now();
+        let mut changed = false;
+        let mut remaining = Vec::with_capacity(self.entries.len());
+
+        for mut entry in std::mem::take(&mut self.entries) {
+            if entry.next_attempt_at > now {
+                remaining.push(entry);
+                continue;
+            }
+
+            changed = true;
+            debug!("Attempting outbox action: {}", entry.action);
+            match perform(&entry.action, secret, device_id).await {
+                Ok(()) => {
+                    info!("Outbox action completed: {}", entry.action);
+                }
+                Err(e) => {
+                    entry.attempts += 1;
+                    entry.next_attempt_at = now + retry_delay_secs(entry.attempts);
+                    entry.last_error = Some(e.to_string());
+                    error!(
+                        "Outbox action failed ({} attempts), retrying in {}s: {}: {}",
+                        entry.attempts,
+                        entry.next_attempt_at - now,
+                        entry.action,
+                        e
+                    );
+                    remaining.push(entry);
+                }
+            }
+        }
+
+        self.entries = remaining;
+        changed
+    }
+}
+
+async fn perform(action: &PendingAction, secret: &str, device_id: &str) -> Result<()> {
+    match action {
+        PendingAction::Acknowledge { receipt } => acknowledge_emergency(secret, receipt).await,
+        PendingAction::UpdateHighestMessage { message_id } => {
+            if message_id.is_empty() {
+                return Err(anyhow!("Empty message ID"));
+            }
+            delete_messages(secret, device_id, message_id).await
+        }
+    }
+}
+
+// Exponential backoff: 5s, 10s, 20s, ... capped at 15 minutes
+fn retry_delay_secs(attempts: u32) -> i64 {
+    let exponent = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE_DELAY_SECS << exponent).min(RETRY_MAX_DELAY_SECS)
+}
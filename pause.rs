@@ -0,0 +1,163 @@
+use crate::types::{Config, Message};
+use chrono::{DateTime, Days, Local, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// "Until tomorrow" ends at this local time the next day
+const TOMORROW_RESUME_HOUR: u32 = 8;
+
+// How long to pause notifications for, as offered in the tray menu
+#[derive(Clone, Copy, Debug)]
+pub enum PauseDuration {
+    ThirtyMinutes,
+    OneHour,
+    UntilTomorrow,
+    Indefinitely,
+}
+
+impl PauseDuration {
+    pub const ALL: [PauseDuration; 4] = [
+        PauseDuration::ThirtyMinutes,
+        PauseDuration::OneHour,
+        PauseDuration::UntilTomorrow,
+        PauseDuration::Indefinitely,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PauseDuration::ThirtyMinutes => "For 30 minutes",
+            PauseDuration::OneHour => "For 1 hour",
+            PauseDuration::UntilTomorrow => "Until tomorrow",
+            PauseDuration::Indefinitely => "Until I resume",
+        }
+    }
+
+    // Short form used by remote commands: "30m", "1h", "tomorrow" or "indefinitely"
+    pub fn parse(text: &str) -> Option<PauseDuration> {
+        match text.to_ascii_lowercase().as_str() {
+            "30m" | "30min" => Some(PauseDuration::ThirtyMinutes),
+            "1h" | "60m" => Some(PauseDuration::OneHour),
+            "tomorrow" => Some(PauseDuration::UntilTomorrow),
+            "indefinitely" | "forever" => Some(PauseDuration::Indefinitely),
+            _ => None,
+        }
+    }
+
+    // Unix time the pause ends at, None for indefinitely
+    pub fn until(self, now: DateTime<Local>) -> Option<i64> {
+        match self {
+            PauseDuration::ThirtyMinutes => Some(now.timestamp() + 30 * 60),
+            PauseDuration::OneHour => Some(now.timestamp() + 60 * 60),
+            PauseDuration::UntilTomorrow => {
+                let resume_at = NaiveTime::from_hms_opt(TOMORROW_RESUME_HOUR, 0, 0)?;
+                let tomorrow = now.date_naive().checked_add_days(Days::new(1))?;
+                // earliest() keeps DST transitions from making this ambiguous or nonexistent
+                Local
+                    .from_local_datetime(&tomorrow.and_time(resume_at))
+                    .earliest()
+                    .map(|time| time.timestamp())
+                    .or(Some(now.timestamp() + 24 * 60 * 60))
+            }
+            PauseDuration::Indefinitely => None,
+        }
+    }
+}
+
+// Persisted in the config so a pause survives a restart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PauseState {
+    pub started_at: i64,
+    pub until: Option<i64>,
+    // Messages that arrived while paused, for the summary when it ends
+    #[serde(default)]
+    pub held: Vec<HeldMessage>,
+}
+
+impl PauseState {
+    pub fn new(duration: PauseDuration, now: DateTime<Local>) -> Self {
+        Self {
+            started_at: now.timestamp(),
+            until: duration.until(now),
+            held: vec![],
+        }
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+
+    pub fn describe(&self, now: &DateTime<Local>) -> String {
+        let Some(until) = self.until.and_then(|ts| Local.timestamp_opt(ts, 0).single()) else {
+            return "Paused until resumed".to_string();
+        };
+
+        match until.date_naive() == now.date_naive() {
+            true => format!("Paused until {}", until.format("%H:%M")),
+            false => format!("Paused until {}", until.format("%a %H:%M")),
+        }
+    }
+
+    pub fn summary(&self) -> Option<String> {
+        summarize(self.held.iter().map(|message| message.app.as_str()), "while paused")
+    }
+}
+
+// e.g. "3 messages arrived while paused: Backups (2), Grafana (1)"
+pub fn summarize<'a>(apps: impl IntoIterator<Item = &'a str>, when: &str) -> Option<String> {
+    let mut per_app: BTreeMap<&str, usize> = BTreeMap::new();
+    for app in apps {
+        *per_app.entry(app).or_default() += 1;
+    }
+
+    let total: usize = per_app.values().sum();
+    if total == 0 {
+        return None;
+    }
+
+    let apps: Vec<String> = per_app
+        .iter()
+        .map(|(app, count)| format!("{} ({})", app, count))
+        .collect();
+
+    Some(format!(
+        "{} message{} arrived {}: {}",
+        total,
+        if total == 1 { "" } else { "s" },
+        when,
+        apps.join(", ")
+    ))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeldMessage {
+    pub id: i64,
+    pub app: String,
+    pub title: Option<String>,
+    pub priority: i32,
+    pub date: i64,
+}
+
+impl From<&Message> for HeldMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            id: message.id,
+            app: message.app.clone(),
+            title: message.title.clone(),
+            priority: message.priority,
+            date: message.date,
+        }
+    }
+}
+
+// Record the message instead of showing it if notifications are paused
+pub fn hold_if_paused(config: &mut Config, message: &Message, now: i64) -> bool {
+    let bypass = config.preferences.pause_bypass_emergency && message.priority >= 2;
+
+    match &mut config.state.pause {
+        Some(pause) if pause.is_active(now) && !bypass => {
+            pause.held.push(HeldMessage::from(message));
+            true
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,160 @@
+use log::{debug, info};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+use tokio::time;
+
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+// How far the clocks may drift apart per check before we assume the machine slept
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(30);
+
+// Why the message feed should drop its connection and resync
+#[derive(Clone, Copy, Debug)]
+pub enum WakeReason {
+    // Wall clock jumped ahead of the monotonic clock
+    ClockJump(Duration),
+    // logind announced the end of a suspend
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    Resumed,
+    // NetworkManager regained full connectivity
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    NetworkChanged,
+}
+
+impl std::fmt::Display for WakeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WakeReason::ClockJump(gap) => write!(f, "clock jumped {}s, probably resumed from sleep", gap.as_secs()),
+            WakeReason::Resumed => write!(f, "resumed from sleep"),
+            WakeReason::NetworkChanged => write!(f, "network connectivity changed"),
+        }
+    }
+}
+
+// Start all available suspend/resume and network watchers
+pub fn spawn_watchers() -> mpsc::Receiver<WakeReason> {
+    let (tx, rx) = mpsc::channel(8);
+
+    tokio::spawn(watch_clock_drift(tx.clone()));
+
+    #[cfg(target_os = "linux")]
+    {
+        tokio::spawn(linux::watch_prepare_for_sleep(tx.clone()));
+        tokio::spawn(linux::watch_network_manager(tx));
+    }
+
+    rx
+}
+
+// Works everywhere: compare how much wall-clock and monotonic time passed across
+// a short sleep. The monotonic clock (and our timer) stops while suspended on
+// Linux, the wall clock doesn't; on platforms where the monotonic clock keeps
+// running, the timer itself overshoots instead.
+async fn watch_clock_drift(tx: mpsc::Sender<WakeReason>) {
+    loop {
+        let wall_before = SystemTime::now();
+        let mono_before = Instant::now();
+
+        time::sleep(CLOCK_CHECK_INTERVAL).await;
+
+        let mono_elapsed = mono_before.elapsed();
+        let wall_elapsed = SystemTime::now()
+            .duration_since(wall_before)
+            .unwrap_or_default();
+
+        let gap = wall_elapsed
+            .saturating_sub(mono_elapsed)
+            .max(mono_elapsed.saturating_sub(CLOCK_CHECK_INTERVAL));
+
+        if gap > CLOCK_JUMP_THRESHOLD {
+            info!("Detected a {}s clock jump", gap.as_secs());
+            if tx.send(WakeReason::ClockJump(gap)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Drop any extra wake-ups that piled up while we were already resyncing
+pub fn drain(rx: &mut mpsc::Receiver<WakeReason>) {
+    while let Ok(reason) = rx.try_recv() {
+        debug!("Ignoring queued wake-up: {}", reason);
+    }
+}
+
+// ============================================================================
+// Linux: logind and NetworkManager signals on the system bus
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::WakeReason;
+    use anyhow::Result;
+    use futures_util::StreamExt;
+    use log::{debug, info, warn};
+    use tokio::sync::mpsc;
+    use zbus::{Connection, MatchRule, MessageStream};
+
+    // NetworkManager state for full internet connectivity
+    const NM_STATE_CONNECTED_GLOBAL: u32 = 70;
+
+    async fn signal_stream(interface: &'static str, member: &'static str) -> Result<MessageStream> {
+        let connection = Connection::system().await?;
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface(interface)?
+            .member(member)?
+            .build();
+
+        Ok(MessageStream::for_match_rule(rule, &connection, None).await?)
+    }
+
+    pub async fn watch_prepare_for_sleep(tx: mpsc::Sender<WakeReason>) {
+        let mut stream = match signal_stream("org.freedesktop.login1.Manager", "PrepareForSleep").await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("logind sleep signal unavailable, relying on clock drift: {}", e);
+                return;
+            }
+        };
+
+        debug!("Watching logind PrepareForSleep");
+        while let Some(message) = stream.next().await {
+            let Ok(message) = message else { continue };
+
+            match message.body().deserialize::<bool>() {
+                Ok(true) => info!("System is going to sleep"),
+                Ok(false) => {
+                    if tx.send(WakeReason::Resumed).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("Unexpected PrepareForSleep payload: {}", e),
+            }
+        }
+    }
+
+    pub async fn watch_network_manager(tx: mpsc::Sender<WakeReason>) {
+        let mut stream = match signal_stream("org.freedesktop.NetworkManager", "StateChanged").await {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("NetworkManager signals unavailable: {}", e);
+                return;
+            }
+        };
+
+        debug!("Watching NetworkManager StateChanged");
+        while let Some(message) = stream.next().await {
+            let Ok(message) = message else { continue };
+
+            match message.body().deserialize::<u32>() {
+                Ok(NM_STATE_CONNECTED_GLOBAL) => {
+                    if tx.send(WakeReason::NetworkChanged).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(state) => debug!("NetworkManager state changed to {}", state),
+                Err(e) => warn!("Unexpected StateChanged payload: {}", e),
+            }
+        }
+    }
+}
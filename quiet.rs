@@ -0,0 +1,303 @@
+use crate::types::Message;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+
+// Recurring quiet hours, e.g. weekdays 22:00-07:00 and all weekend
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuietHoursConfig {
+    pub periods: Vec<QuietPeriod>,
+    // Messages at or above this priority are never held or silenced
+    pub min_priority: i32,
+    pub action: QuietAction,
+}
+
+impl Default for QuietHoursConfig {
+    fn default() -> Self {
+        Self {
+            periods: vec![],
+            min_priority: 2,
+            action: QuietAction::Silent,
+        }
+    }
+}
+
+// One recurring window; an `end` before `start` runs past midnight into the
+// next day, and `start` == `end` covers the whole day
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuietPeriod {
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuietAction {
+    // Keep the message and show it once quiet hours are over
+    Hold,
+    // Show it right away without sound
+    Silent,
+}
+
+// What to do with a message given the quiet hours schedule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuietDecision {
+    Show,
+    ShowSilently,
+    Hold,
+}
+
+// Source of "now", so the schedule can be evaluated at any time (and in any time zone)
+pub trait Clock<Tz: TimeZone = Local>: Send + Sync {
+    fn now(&self) -> DateTime<Tz>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+impl QuietPeriod {
+    // Works on local wall-clock time, so DST shifts move the window with the
+    // clock instead of by an hour
+    fn contains(&self, at: NaiveDateTime) -> bool {
+        let (day, time) = (at.weekday(), at.time());
+
+        if self.start == self.end {
+            self.days.contains(&day)
+        } else if self.start < self.end {
+            self.days.contains(&day) && self.start <= time && time < self.end
+        } else {
+            (self.days.contains(&day) && time >= self.start)
+                || (self.days.contains(&day.pred()) && time < self.end)
+        }
+    }
+}
+
+impl QuietHoursConfig {
+    pub fn is_quiet<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> bool {
+        let at = at.naive_local();
+        self.periods.iter().any(|period| period.contains(at))
+    }
+
+    pub fn decide<Tz: TimeZone>(&self, message: &Message, at: &DateTime<Tz>) -> QuietDecision {
+        if message.priority >= self.min_priority || !self.is_quiet(at) {
+            return QuietDecision::Show;
+        }
+
+        match self.action {
+            QuietAction::Hold => QuietDecision::Hold,
+            QuietAction::Silent => QuietDecision::ShowSilently,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, FixedOffset};
+
+    // Always the same instant, with the UTC offset the local clock had at that moment
+    struct FixedClock(DateTime<FixedOffset>);
+
+    impl Clock<FixedOffset> for FixedClock {
+        fn now(&self) -> DateTime<FixedOffset> {
+            self.0
+        }
+    }
+
+    fn clock(rfc3339: &str) -> FixedClock {
+        FixedClock(DateTime::parse_from_rfc3339(rfc3339).unwrap())
+    }
+
+    fn period(days: &[Weekday], start: &str, end: &str) -> QuietPeriod {
+        QuietPeriod {
+            days: days.to_vec(),
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+        }
+    }
+
+    fn quiet_hours(periods: Vec<QuietPeriod>) -> QuietHoursConfig {
+        QuietHoursConfig {
+            periods,
+            ..Default::default()
+        }
+    }
+
+    fn assert_quiet(config: &QuietHoursConfig, cases: &[(&str, bool)]) {
+        for (at, expected) in cases {
+            assert_eq!(config.is_quiet(&clock(at).now()), *expected, "at {}", at);
+        }
+    }
+
+    // 2026-01-16 is a Friday
+    #[test]
+    fn overnight_period_runs_past_midnight() {
+        let config = quiet_hours(vec![period(&[Weekday::Fri], "22:00:00", "07:00:00")]);
+        assert_quiet(
+            &config,
+            &[
+                ("2026-01-16T21:59:59+01:00", false),
+                ("2026-01-16T22:00:00+01:00", true),
+                ("2026-01-16T23:59:59+01:00", true),
+                ("2026-01-17T00:00:00+01:00", true),
+                ("2026-01-17T06:59:59+01:00", true),
+                ("2026-01-17T07:00:00+01:00", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn days_are_the_ones_a_period_starts_on() {
+        let config = quiet_hours(vec![period(&[Weekday::Fri], "22:00:00", "07:00:00")]);
+        assert_quiet(
+            &config,
+            &[
+                // Friday morning belongs to Thursday night, which isn't listed
+                ("2026-01-16T06:00:00+01:00", false),
+                // Saturday night isn't listed either, Saturday morning is Friday's
+                ("2026-01-17T23:00:00+01:00", false),
+                ("2026-01-18T06:00:00+01:00", false),
+            ],
+        );
+
+        let weekend = quiet_hours(vec![period(&[Weekday::Sat, Weekday::Sun], "00:00:00", "00:00:00")]);
+        assert_quiet(
+            &weekend,
+            &[
+                ("2026-01-16T23:59:59+01:00", false),
+                ("2026-01-17T00:00:00+01:00", true),
+                ("2026-01-18T23:59:59+01:00", true),
+                ("2026-01-19T00:00:00+01:00", false),
+            ],
+        );
+
+        let office = quiet_hours(vec![period(
+            &[Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+            "09:00:00",
+            "17:00:00",
+        )]);
+        assert_quiet(
+            &office,
+            &[
+                ("2026-01-16T08:59:59+01:00", false),
+                ("2026-01-16T09:00:00+01:00", true),
+                ("2026-01-16T16:59:59+01:00", true),
+                ("2026-01-16T17:00:00+01:00", false),
+                ("2026-01-17T12:00:00+01:00", false),
+            ],
+        );
+    }
+
+    #[test]
+    fn any_period_makes_it_quiet() {
+        let config = quiet_hours(vec![
+            period(&[Weekday::Fri], "12:00:00", "13:00:00"),
+            period(&[Weekday::Fri], "22:00:00", "07:00:00"),
+        ]);
+        assert_quiet(
+            &config,
+            &[
+                ("2026-01-16T12:30:00+01:00", true),
+                ("2026-01-16T18:00:00+01:00", false),
+                ("2026-01-16T23:00:00+01:00", true),
+            ],
+        );
+        assert_quiet(&quiet_hours(vec![]), &[("2026-01-16T23:00:00+01:00", false)]);
+    }
+
+    #[test]
+    fn priority_threshold_and_action() {
+        let mut config = quiet_hours(vec![period(&[Weekday::Fri], "22:00:00", "07:00:00")]);
+        config.min_priority = 1;
+        let (quiet, awake) = (clock("2026-01-16T23:00:00+01:00"), clock("2026-01-16T12:00:00+01:00"));
+        let message = |priority| Message { priority, ..Default::default() };
+
+        let cases = [
+            // (action, priority, during quiet hours, expected)
+            (QuietAction::Hold, -2, true, QuietDecision::Hold),
+            (QuietAction::Hold, 0, true, QuietDecision::Hold),
+            (QuietAction::Hold, 1, true, QuietDecision::Show),
+            (QuietAction::Hold, 2, true, QuietDecision::Show),
+            (QuietAction::Hold, 0, false, QuietDecision::Show),
+            (QuietAction::Silent, 0, true, QuietDecision::ShowSilently),
+            (QuietAction::Silent, 1, true, QuietDecision::Show),
+            (QuietAction::Silent, 0, false, QuietDecision::Show),
+        ];
+
+        for (action, priority, during, expected) in cases {
+            config.action = action;
+            let at = if during { quiet.now() } else { awake.now() };
+            assert_eq!(config.decide(&message(priority), &at), expected, "{:?} priority {} quiet {}", action, priority, during);
+        }
+    }
+
+    // Europe/Berlin: on 2026-03-29 the clocks go from 02:00 CET (+01:00) to 03:00 CEST (+02:00)
+    #[test]
+    fn spring_forward_keeps_wall_clock_times() {
+        let config = quiet_hours(vec![period(&[Weekday::Sat], "22:00:00", "07:00:00")]);
+        assert_quiet(
+            &config,
+            &[
+                ("2026-03-28T22:00:00+01:00", true),
+                ("2026-03-29T01:59:59+01:00", true),
+                ("2026-03-29T03:00:00+02:00", true),
+                ("2026-03-29T06:59:59+02:00", true),
+                ("2026-03-29T07:00:00+02:00", false),
+            ],
+        );
+
+        // The night is an hour shorter in real time
+        let (start, end) = (clock("2026-03-28T22:00:00+01:00").now(), clock("2026-03-29T07:00:00+02:00").now());
+        assert_eq!(end - start, Duration::hours(8));
+
+        // A period starting in the skipped hour begins when the clock jumps past its start
+        let skipped = quiet_hours(vec![period(&[Weekday::Sun], "02:30:00", "04:00:00")]);
+        assert_quiet(
+            &skipped,
+            &[
+                ("2026-03-29T01:59:59+01:00", false),
+                ("2026-03-29T03:00:00+02:00", true),
+                ("2026-03-29T03:59:59+02:00", true),
+                ("2026-03-29T04:00:00+02:00", false),
+            ],
+        );
+    }
+
+    // Europe/Berlin: on 2026-10-25 the clocks go from 03:00 CEST (+02:00) back to 02:00 CET (+01:00)
+    #[test]
+    fn fall_back_keeps_wall_clock_times() {
+        let config = quiet_hours(vec![period(&[Weekday::Sat], "22:00:00", "07:00:00")]);
+        assert_quiet(
+            &config,
+            &[
+                ("2026-10-24T22:00:00+02:00", true),
+                ("2026-10-25T02:30:00+02:00", true),
+                ("2026-10-25T02:30:00+01:00", true),
+                ("2026-10-25T06:59:59+01:00", true),
+                ("2026-10-25T07:00:00+01:00", false),
+            ],
+        );
+
+        // The night is an hour longer in real time
+        let (start, end) = (clock("2026-10-24T22:00:00+02:00").now(), clock("2026-10-25T07:00:00+01:00").now());
+        assert_eq!(end - start, Duration::hours(10));
+
+        // A period ending in the repeated hour covers both passes through it
+        let repeated = quiet_hours(vec![period(&[Weekday::Sun], "01:00:00", "02:30:00")]);
+        assert_quiet(
+            &repeated,
+            &[
+                ("2026-10-25T02:15:00+02:00", true),
+                ("2026-10-25T02:45:00+02:00", false),
+                ("2026-10-25T02:15:00+01:00", true),
+                ("2026-10-25T02:45:00+01:00", false),
+            ],
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use crate::toast::{NotifyOptions, UrgencyLevel};
+use crate::types::Message;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path, sync::LazyLock};
+
+const RULES_FILENAME: &str = "rules.json";
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(app|title|message|priority|url)\}").unwrap());
+
+// On-disk format of rules.json
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: Option<String>,
+    #[serde(default, rename = "match")]
+    matcher: MatchSpec,
+    actions: Vec<Action>,
+    // Don't look at later rules once this one matched
+    #[serde(default)]
+    stop: bool,
+}
+
+// Message filter shared by rules, hooks and sinks. Every field that is set has
+// to match; an empty matcher matches everything.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<PriorityMatch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PriorityMatch {
+    Exact(i32),
+    Range { min: Option<i32>, max: Option<i32> },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Action {
+    // Don't show a notification (hooks and sinks still run)
+    Suppress,
+    Urgency { urgency: UrgencyLevel },
+    // Templates may use {app}, {title}, {message}, {priority} and {url}
+    RewriteTitle { template: String },
+    RewriteBody { template: String },
+    Sticky,
+    Sound { sound: String },
+    RunHook { hook: String },
+    Forward { sink: String },
+}
+
+// Compiled form of a MatchSpec
+#[derive(Debug)]
+pub struct Matcher {
+    app: Option<String>,
+    aid: Option<i64>,
+    priority: (i32, i32),
+    title: Option<Regex>,
+    message: Option<Regex>,
+    url: Option<Regex>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    matcher: Matcher,
+    actions: Vec<Action>,
+    stop: bool,
+}
+
+// What the matching rules decided for one message
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub suppress: bool,
+    pub options: NotifyOptions,
+    // Hooks requested by run_hook actions
+    pub hooks: Vec<String>,
+    // Sinks requested by forward actions
+    pub sinks: Vec<String>,
+    // Names of the rules that matched, for logging
+    pub matched: Vec<String>,
+}
+
+// Validated rules, applied in file order
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    // Load and validate rules.json; a missing file means no rules. Hook and sink
+    // names referenced by rules have to exist.
+    pub fn load(config_dir: &Path, hooks: &[&str], sinks: &[&str]) -> Result<Self> {
+        let path = config_dir.join(RULES_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(&path)?;
+        let rules = Self::parse(&data, hooks, sinks).with_context(|| format!("Invalid {}", path.display()))?;
+
+        info!("Loaded {} message rule(s)", rules.rules.len());
+        Ok(rules)
+    }
+
+    pub fn parse(data: &str, hooks: &[&str], sinks: &[&str]) -> Result<Self> {
+        let file: RulesFile = serde_json::from_str(data)?;
+
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(index, spec)| {
+                let name = spec.name.clone().unwrap_or_else(|| format!("rule {}", index + 1));
+                Rule::compile(name.clone(), spec, hooks, sinks).with_context(|| format!("In {}", name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    // Run the message through every matching rule, rewriting it in place
+    pub fn apply(&self, message: &mut Message) -> Outcome {
+        let mut outcome = Outcome::default();
+
+        for rule in &self.rules {
+            if !rule.matcher.matches(message) {
+                continue;
+            }
+
+            outcome.matched.push(rule.name.clone());
+            for action in &rule.actions {
+                match action {
+                    Action::Suppress => outcome.suppress = true,
+                    Action::Urgency { urgency } => outcome.options.urgency = Some(*urgency),
+                    Action::RewriteTitle { template } => message.title = Some(render(template, message)),
+                    Action::RewriteBody { template } => message.message = render(template, message),
+                    Action::Sticky => outcome.options.sticky = true,
+                    Action::Sound { sound } => outcome.options.sound = Some(sound.clone()),
+                    Action::RunHook { hook } => outcome.hooks.push(hook.clone()),
+                    Action::Forward { sink } => outcome.sinks.push(sink.clone()),
+                }
+            }
+
+            if rule.stop {
+                break;
+            }
+        }
+
+        outcome
+    }
+}
+
+impl Rule {
+    fn compile(name: String, spec: RuleSpec, hooks: &[&str], sinks: &[&str]) -> Result<Self> {
+        if spec.actions.is_empty() {
+            return Err(anyhow!("Rule has no actions"));
+        }
+
+        for action in &spec.actions {
+            match action {
+                Action::RewriteTitle { template } | Action::RewriteBody { template } if template.is_empty() => {
+                    return Err(anyhow!("Rewrite template is empty"));
+                }
+                Action::Sound { sound } if sound.is_empty() => return Err(anyhow!("Sound name is empty")),
+                Action::RunHook { hook } if !hooks.contains(&hook.as_str()) => {
+                    return Err(anyhow!("Unknown hook '{}'", hook));
+                }
+                Action::Forward { sink } if !sinks.contains(&sink.as_str()) => {
+                    return Err(anyhow!("Unknown sink '{}'", sink));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            name,
+            matcher: Matcher::compile(&spec.matcher)?,
+            actions: spec.actions,
+            stop: spec.stop,
+        })
+    }
+}
+
+impl Matcher {
+    pub fn compile(spec: &MatchSpec) -> Result<Self> {
+        let priority = match spec.priority {
+            None => (i32::MIN, i32::MAX),
+            Some(PriorityMatch::Exact(priority)) => (priority, priority),
+            Some(PriorityMatch::Range { min, max }) => (min.unwrap_or(i32::MIN), max.unwrap_or(i32::MAX)),
+        };
+        if priority.0 > priority.1 {
+            return Err(anyhow!("Priority range is empty (min {} > max {})", priority.0, priority.1));
+        }
+
+        Ok(Self {
+            app: spec.app.clone(),
+            aid: spec.aid,
+            priority,
+            title: compile_regex("title", spec.title.as_deref())?,
+            message: compile_regex("message", spec.message.as_deref())?,
+            url: compile_regex("url", spec.url.as_deref())?,
+        })
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        let text_matches = |regex: &Option<Regex>, text: Option<&str>| match regex {
+            Some(regex) => regex.is_match(text.unwrap_or_default()),
+            None => true,
+        };
+
+        self.app.as_ref().is_none_or(|app| app.eq_ignore_ascii_case(&message.app))
+            && self.aid.is_none_or(|aid| aid == message.aid)
+            && (self.priority.0..=self.priority.1).contains(&message.priority)
+            && text_matches(&self.title, message.title.as_deref())
+            && text_matches(&self.message, Some(&message.message))
+            && text_matches(&self.url, message.url.as_deref())
+    }
+}
+
+fn compile_regex(field: &str, pattern: Option<&str>) -> Result<Option<Regex>> {
+    pattern
+        .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid {} regex", field)))
+        .transpose()
+}
+
+// Single pass, so placeholders inside the substituted text are left alone
+fn render(template: &str, message: &Message) -> String {
+    PLACEHOLDER
+        .replace_all(template, |captures: &regex::Captures| match &captures[1] {
+            "app" => message.app.clone(),
+            "title" => message.title.clone().unwrap_or_else(|| message.app.clone()),
+            "message" => message.message.clone(),
+            "priority" => message.priority.to_string(),
+            "url" => message.url.clone().unwrap_or_default(),
+            _ => captures[0].to_string(),
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Message {
+        Message {
+            id: 1,
+            app: "Backup".to_string(),
+            aid: 42,
+            priority: 0,
+            title: Some("Disk almost full".to_string()),
+            message: "/home is at 93%".to_string(),
+            url: Some("https://nas.local/status".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn rules(json: &str) -> Rules {
+        Rules::parse(json, &["notify-send"], &["ops"]).unwrap_or_else(|e| panic!("{}: {:#}", json, e))
+    }
+
+    // Everything a rule can change, flattened so cases can be compared in one go
+    #[derive(Debug, Default, PartialEq)]
+    struct Effect {
+        matched: Vec<&'static str>,
+        suppress: bool,
+        title: Option<&'static str>,
+        message: &'static str,
+        urgency: Option<UrgencyLevel>,
+        sticky: bool,
+        sound: Option<&'static str>,
+        hooks: Vec<&'static str>,
+        sinks: Vec<&'static str>,
+    }
+
+    fn effect_of(rules: &Rules) -> (Outcome, Message) {
+        let mut message = message();
+        let outcome = rules.apply(&mut message);
+        (outcome, message)
+    }
+
+    fn assert_effect(name: &str, json: &str, expected: Effect) {
+        let (outcome, message) = effect_of(&rules(json));
+        assert_eq!(outcome.matched, expected.matched, "{}: matched", name);
+        assert_eq!(outcome.suppress, expected.suppress, "{}: suppress", name);
+        assert_eq!(message.title.as_deref(), expected.title, "{}: title", name);
+        assert_eq!(message.message, expected.message, "{}: message", name);
+        assert_eq!(outcome.options.urgency, expected.urgency, "{}: urgency", name);
+        assert_eq!(outcome.options.sticky, expected.sticky, "{}: sticky", name);
+        assert_eq!(outcome.options.sound.as_deref(), expected.sound, "{}: sound", name);
+        assert_eq!(outcome.hooks, expected.hooks, "{}: hooks", name);
+        assert_eq!(outcome.sinks, expected.sinks, "{}: sinks", name);
+    }
+
+    // Unchanged message, nothing matched
+    fn untouched() -> Effect {
+        Effect {
+            title: Some("Disk almost full"),
+            message: "/home is at 93%",
+            ..Default::default()
+        }
+    }
+
+    // Matched by a rule with nothing visible in the outcome
+    fn matched(names: &[&'static str]) -> Effect {
+        Effect {
+            matched: names.to_vec(),
+            ..untouched()
+        }
+    }
+
+    #[test]
+    fn matches_each_field() {
+        let cases = [
+            // (match, matches the message)
+            (r#"{}"#, true),
+            // app is a literal, compared case-insensitively
+            (r#"{"app": "backup"}"#, true),
+            (r#"{"app": "BACKUP"}"#, true),
+            (r#"{"app": "Back"}"#, false),
+            (r#"{"app": "Back.*"}"#, false),
+            (r#"{"aid": 42}"#, true),
+            (r#"{"aid": 43}"#, false),
+            (r#"{"priority": 0}"#, true),
+            (r#"{"priority": 1}"#, false),
+            (r#"{"priority": {"min": 0}}"#, true),
+            (r#"{"priority": {"min": 1}}"#, false),
+            (r#"{"priority": {"max": -1}}"#, false),
+            (r#"{"priority": {"min": -2, "max": 2}}"#, true),
+            // title, message and url are regexes, found anywhere unless anchored
+            (r#"{"title": "almost"}"#, true),
+            (r#"{"title": "^Disk .* full$"}"#, true),
+            (r#"{"title": "^almost"}"#, false),
+            (r#"{"title": "(?i)DISK"}"#, true),
+            (r#"{"title": "DISK"}"#, false),
+            (r#"{"message": "at 9[0-9]%"}"#, true),
+            (r#"{"message": "at 8[0-9]%"}"#, false),
+            // A regex metacharacter is not a literal
+            (r#"{"message": "93."}"#, true),
+            (r#"{"message": "93\\.%"}"#, false),
+            (r#"{"url": "^https://nas\\.local/"}"#, true),
+            (r#"{"url": "example\\.com"}"#, false),
+            // Every field has to match
+            (r#"{"app": "backup", "priority": 0, "title": "Disk"}"#, true),
+            (r#"{"app": "backup", "priority": 0, "title": "CPU"}"#, false),
+        ];
+
+        for (spec, expected) in cases {
+            let spec: MatchSpec = serde_json::from_str(spec).unwrap();
+            let matcher = Matcher::compile(&spec).unwrap();
+            assert_eq!(matcher.matches(&message()), expected, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn missing_optional_fields_only_match_patterns_that_allow_empty_text() {
+        let mut message = message();
+        message.title = None;
+        message.url = None;
+
+        let cases = [(r#"{"title": "Disk"}"#, false), (r#"{"title": "^$"}"#, true), (r#"{"url": "."}"#, false)];
+        for (spec, expected) in cases {
+            let matcher = Matcher::compile(&serde_json::from_str(spec).unwrap()).unwrap();
+            assert_eq!(matcher.matches(&message), expected, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn applies_actions() {
+        let cases = [
+            ("no rules", r#"{"rules": []}"#, untouched()),
+            (
+                "no match",
+                r#"{"rules": [{"match": {"app": "Mail"}, "actions": [{"action": "suppress"}]}]}"#,
+                untouched(),
+            ),
+            (
+                "suppress",
+                r#"{"rules": [{"name": "mute", "actions": [{"action": "suppress"}]}]}"#,
+                Effect { suppress: true, ..matched(&["mute"]) },
+            ),
+            (
+                "rewrite title",
+                r#"{"rules": [{"name": "r", "actions": [{"action": "rewrite_title", "template": "[{app}] {title}"}]}]}"#,
+                Effect { title: Some("[Backup] Disk almost full"), ..matched(&["r"]) },
+            ),
+            (
+                "rewrite body with every placeholder",
+                r#"{"rules": [{"name": "r", "actions": [
+                    {"action": "rewrite_body", "template": "{app}/{title}/{message}/{priority}/{url}/{unknown}"}
+                ]}]}"#,
+                Effect {
+                    message: "Backup/Disk almost full//home is at 93%/0/https://nas.local/status/{unknown}",
+                    ..matched(&["r"])
+                },
+            ),
+            (
+                "urgency (priority change)",
+                r#"{"rules": [{"name": "r", "actions": [{"action": "urgency", "urgency": "critical"}]}]}"#,
+                Effect { urgency: Some(UrgencyLevel::Critical), ..matched(&["r"]) },
+            ),
+            (
+                "sticky and sound",
+                r#"{"rules": [{"name": "r", "actions": [{"action": "sticky"}, {"action": "sound", "sound": "bell"}]}]}"#,
+                Effect { sticky: true, sound: Some("bell"), ..matched(&["r"]) },
+            ),
+            (
+                "hook and forward routing",
+                r#"{"rules": [{"name": "r", "actions": [
+                    {"action": "run_hook", "hook": "notify-send"},
+                    {"action": "forward", "sink": "ops"}
+                ]}]}"#,
+                Effect { hooks: vec!["notify-send"], sinks: vec!["ops"], ..matched(&["r"]) },
+            ),
+            (
+                "unnamed rules are numbered",
+                r#"{"rules": [{"match": {"app": "Mail"}, "actions": [{"action": "sticky"}]}, {"actions": [{"action": "sticky"}]}]}"#,
+                Effect { sticky: true, ..matched(&["rule 2"]) },
+            ),
+        ];
+
+        for (name, json, expected) in cases {
+            assert_effect(name, json, expected);
+        }
+    }
+
+    #[test]
+    fn applies_matching_rules_in_order_until_one_stops() {
+        let cases = [
+            (
+                "all matching rules apply, later ones win",
+                r#"{"rules": [
+                    {"name": "a", "actions": [{"action": "urgency", "urgency": "low"}, {"action": "forward", "sink": "ops"}]},
+                    {"name": "skipped", "match": {"priority": 2}, "actions": [{"action": "suppress"}]},
+                    {"name": "b", "actions": [{"action": "urgency", "urgency": "critical"}, {"action": "forward", "sink": "ops"}]}
+                ]}"#,
+                Effect { urgency: Some(UrgencyLevel::Critical), sinks: vec!["ops", "ops"], ..matched(&["a", "b"]) },
+            ),
+            (
+                "a rewrite is seen by later rules",
+                r#"{"rules": [
+                    {"name": "a", "actions": [{"action": "rewrite_title", "template": "URGENT {title}"}]},
+                    {"name": "b", "match": {"title": "^URGENT"}, "actions": [{"action": "sticky"}]},
+                    {"name": "c", "match": {"title": "^Disk"}, "actions": [{"action": "suppress"}]}
+                ]}"#,
+                Effect { title: Some("URGENT Disk almost full"), sticky: true, ..matched(&["a", "b"]) },
+            ),
+            (
+                "stop makes the first match final",
+                r#"{"rules": [
+                    {"name": "skipped", "match": {"app": "Mail"}, "stop": true, "actions": [{"action": "suppress"}]},
+                    {"name": "first", "stop": true, "actions": [{"action": "sticky"}]},
+                    {"name": "never", "actions": [{"action": "suppress"}]}
+                ]}"#,
+                Effect { sticky: true, ..matched(&["first"]) },
+            ),
+            (
+                "stop only applies when its rule matched",
+                r#"{"rules": [
+                    {"name": "a", "match": {"priority": 2}, "stop": true, "actions": [{"action": "suppress"}]},
+                    {"name": "b", "actions": [{"action": "sticky"}]}
+                ]}"#,
+                Effect { sticky: true, ..matched(&["b"]) },
+            ),
+        ];
+
+        for (name, json, expected) in cases {
+            assert_effect(name, json, expected);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rules_on_load() {
+        let cases = [
+            (r#"{"rules": [{"name": "x", "actions": []}]}"#, "In x: Rule has no actions"),
+            (r#"{"rules": [{"actions": [{"action": "run_hook", "hook": "nope"}]}]}"#, "In rule 1: Unknown hook 'nope'"),
+            (r#"{"rules": [{"actions": [{"action": "forward", "sink": "nope"}]}]}"#, "In rule 1: Unknown sink 'nope'"),
+            (r#"{"rules": [{"match": {"title": "(unclosed"}, "actions": [{"action": "suppress"}]}]}"#, "Invalid title regex"),
+            (r#"{"rules": [{"match": {"message": "a{2,1}"}, "actions": [{"action": "suppress"}]}]}"#, "Invalid message regex"),
+            (r#"{"rules": [{"match": {"url": "["}, "actions": [{"action": "suppress"}]}]}"#, "Invalid url regex"),
+            (r#"{"rules": [{"match": {"priority": {"min": 2, "max": 1}}, "actions": [{"action": "suppress"}]}]}"#, "Priority range is empty"),
+            (r#"{"rules": [{"actions": [{"action": "rewrite_title", "template": ""}]}]}"#, "Rewrite template is empty"),
+            (r#"{"rules": [{"actions": [{"action": "sound", "sound": ""}]}]}"#, "Sound name is empty"),
+            (r#"{"rules": [{"actions": [{"action": "explode"}]}]}"#, "unknown variant `explode`"),
+            (r#"{"rules": [{"actions": [{"action": "suppress"}], "when": "now"}]}"#, "unknown field `when`"),
+            (r#"{"rules": [{"match": {"sender": "x"}, "actions": [{"action": "suppress"}]}]}"#, "unknown field `sender`"),
+        ];
+
+        for (json, expected) in cases {
+            let error = Rules::parse(json, &["notify-send"], &["ops"]).expect_err(json);
+            let error = format!("{:#}", error);
+            assert!(error.contains(expected), "{}: expected '{}' in '{}'", json, expected, error);
+        }
+    }
+
+    #[test]
+    fn loads_from_the_config_dir() {
+        let config_dir = tempfile::tempdir().unwrap();
+        assert!(Rules::load(config_dir.path(), &[], &[]).unwrap().rules.is_empty());
+
+        fs::write(config_dir.path().join(RULES_FILENAME), r#"{"rules": [{"actions": [{"action": "suppress"}]}]}"#).unwrap();
+        assert_eq!(Rules::load(config_dir.path(), &[], &[]).unwrap().rules.len(), 1);
+
+        fs::write(config_dir.path().join(RULES_FILENAME), r#"{"rules": [{"actions": [{"action": "forward", "sink": "ops"}]}]}"#).unwrap();
+        let error = format!("{:#}", Rules::load(config_dir.path(), &[], &[]).unwrap_err());
+        assert!(error.starts_with("Invalid ") && error.ends_with("Unknown sink 'ops'"), "{}", error);
+    }
+}
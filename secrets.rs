@@ -0,0 +1,348 @@
+use crate::types::Config;
+use crate::utils::{save_config, write_atomic};
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const KEYRING_SERVICE: &str = "miniover";
+const KEYRING_USER: &str = "credentials";
+const ENCRYPTED_FILENAME: &str = "credentials.enc";
+const PASSPHRASE_ENV: &str = "MINIOVER_PASSPHRASE";
+// Key files hold random bytes, anything shorter is more likely a typo than a key
+const MIN_KEY_FILE_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+
+// Where the Pushover credentials are kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialStore {
+    // The keyring when there is one, otherwise the encrypted file
+    #[default]
+    Auto,
+    // Secret Service on Linux, Credential Manager on Windows
+    Keyring,
+    // credentials.enc, unlocked by key_file or a passphrase
+    File,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CredentialsConfig {
+    pub store: CredentialStore,
+    // Random bytes that unlock credentials.enc; without it a passphrase is asked for
+    // (or taken from MINIOVER_PASSPHRASE)
+    pub key_file: Option<PathBuf>,
+}
+
+// The part of Config that never goes into state.json
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub user_key: Option<String>,
+    pub secret: Option<String>,
+    pub device_id: Option<String>,
+}
+
+impl Credentials {
+    fn is_empty(&self) -> bool {
+        self.user_key.is_none() && self.secret.is_none() && self.device_id.is_none()
+    }
+}
+
+pub trait SecretStore {
+    // For log messages, e.g. "the system keyring"
+    fn name(&self) -> String;
+    fn load(&self) -> Result<Option<Credentials>>;
+    fn save(&self, credentials: &Credentials) -> Result<()>;
+}
+
+// ============================================================================
+// System keyring
+// ============================================================================
+
+struct KeyringStore {
+    entry: keyring::Entry,
+}
+
+impl KeyringStore {
+    // Fails if there is no keyring to talk to, e.g. no Secret Service on a headless machine
+    fn open() -> Result<Self> {
+        let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?;
+        match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(Self { entry }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self) -> Result<()> {
+        match self.entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn name(&self) -> String {
+        "the system keyring".to_string()
+    }
+
+    fn load(&self) -> Result<Option<Credentials>> {
+        match self.entry.get_password() {
+            Ok(json) => Ok(Some(serde_json::from_str(&json).context("Invalid credentials in the keyring")?)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, credentials: &Credentials) -> Result<()> {
+        self.entry.set_password(&serde_json::to_string(credentials)?)?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Encrypted file fallback
+// ============================================================================
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Kdf {
+    // Passphrase stretched with Argon2id and the stored salt
+    Argon2id,
+    // SHA-256 of the key file
+    KeyFile,
+}
+
+// On-disk layout of credentials.enc, binary fields base64 encoded
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    kdf: Kdf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+struct EncryptedFileStore {
+    path: PathBuf,
+    kdf: Kdf,
+    salt: Option<Vec<u8>>,
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptedFileStore {
+    fn open(settings: &CredentialsConfig, config_dir: &Path, interactive: bool) -> Result<Self> {
+        let path = config_dir.join(ENCRYPTED_FILENAME);
+        let existing = match fs::read_to_string(&path) {
+            Ok(data) => Some(
+                serde_json::from_str::<EncryptedFile>(&data)
+                    .with_context(|| format!("{} is corrupt", path.display()))?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        let (kdf, salt, key) = match &settings.key_file {
+            Some(key_file) => (Kdf::KeyFile, None, key_from_file(key_file)?),
+            None => {
+                // Keep the salt of an existing file so the same passphrase opens it
+                let salt = match existing.as_ref().and_then(|file| file.salt.as_ref()) {
+                    Some(salt) => BASE64.decode(salt).context("Invalid salt in credentials file")?,
+                    None => {
+                        let mut salt = vec![0; SALT_LEN];
+                        OsRng.fill_bytes(&mut salt);
+                        salt
+                    }
+                };
+                let key = key_from_passphrase(&passphrase(interactive)?, &salt)?;
+                (Kdf::Argon2id, Some(salt), key)
+            }
+        };
+
+        if let Some(file) = &existing
+            && file.kdf != kdf
+        {
+            return Err(anyhow!(
+                "{} is locked with a {}, but a {} is configured",
+                path.display(),
+                describe(file.kdf),
+                describe(kdf)
+            ));
+        }
+
+        Ok(Self {
+            path,
+            kdf,
+            salt,
+            cipher: ChaCha20Poly1305::new(&key),
+        })
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load(&self) -> Result<Option<Credentials>> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let file: EncryptedFile = serde_json::from_str(&data)?;
+
+        let nonce = BASE64.decode(&file.nonce)?;
+        if nonce.len() != 12 {
+            return Err(anyhow!("Invalid nonce in {}", self.path.display()));
+        }
+        let ciphertext = BASE64.decode(&file.ciphertext)?;
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| anyhow!("Could not decrypt {}, wrong {}?", self.path.display(), describe(self.kdf)))?;
+
+        Ok(Some(serde_json::from_slice(&plaintext)?))
+    }
+
+    fn save(&self, credentials: &Credentials) -> Result<()> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, serde_json::to_vec(credentials)?.as_slice())
+            .map_err(|_| anyhow!("Failed to encrypt credentials"))?;
+
+        let file = EncryptedFile {
+            kdf: self.kdf,
+            salt: self.salt.as_ref().map(|salt| BASE64.encode(salt)),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        write_atomic(&self.path, serde_json::to_string_pretty(&file)?.as_bytes())
+    }
+}
+
+fn describe(kdf: Kdf) -> &'static str {
+    match kdf {
+        Kdf::Argon2id => "passphrase",
+        Kdf::KeyFile => "key file",
+    }
+}
+
+fn key_from_file(key_file: &Path) -> Result<Key> {
+    let bytes = fs::read(key_file).with_context(|| format!("Failed to read key file {}", key_file.display()))?;
+    if bytes.len() < MIN_KEY_FILE_LEN {
+        return Err(anyhow!(
+            "Key file {} is too short, it needs at least {} random bytes",
+            key_file.display(),
+            MIN_KEY_FILE_LEN
+        ));
+    }
+    Ok(Sha256::digest(&bytes))
+}
+
+fn key_from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = Key::default();
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive key from passphrase: {}", e))?;
+    Ok(key)
+}
+
+// From MINIOVER_PASSPHRASE, or asked for on the terminal when there is one
+fn passphrase(interactive: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    #[cfg(target_os = "linux")]
+    if interactive && std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        let passphrase = rpassword::prompt_password("Passphrase for miniover credentials: ")?;
+        if !passphrase.is_empty() {
+            return Ok(passphrase);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = interactive;
+
+    Err(anyhow!(
+        "No keyring available and no passphrase for the credentials file; set {} or credentials.key_file",
+        PASSPHRASE_ENV
+    ))
+}
+
+// ============================================================================
+// Entry points
+// ============================================================================
+
+// Pick the store configured in settings.toml; `interactive` allows asking for a passphrase
+pub fn open(config: &Config, config_dir: &Path, interactive: bool) -> Result<Box<dyn SecretStore>> {
+    let settings = &config.preferences.credentials;
+    match settings.store {
+        CredentialStore::Keyring => Ok(Box::new(KeyringStore::open().context("System keyring unavailable")?)),
+        CredentialStore::File => Ok(Box::new(EncryptedFileStore::open(settings, config_dir, interactive)?)),
+        // Stick with the file once there is one, the keyring may only come and go with the desktop
+        CredentialStore::Auto if config_dir.join(ENCRYPTED_FILENAME).exists() => {
+            Ok(Box::new(EncryptedFileStore::open(settings, config_dir, interactive)?))
+        }
+        CredentialStore::Auto => match KeyringStore::open() {
+            Ok(store) => Ok(Box::new(store)),
+            Err(e) => {
+                info!("System keyring unavailable ({}), using an encrypted file", e);
+                Ok(Box::new(EncryptedFileStore::open(settings, config_dir, interactive)?))
+            }
+        },
+    }
+}
+
+// Fill in the credentials from the store, first moving any still in config.json over
+pub fn load_credentials(store: &dyn SecretStore, config: &mut Config, state_dir: &Path) -> Result<()> {
+    if !config.credentials.is_empty() {
+        store.save(&config.credentials).context("Failed to move credentials out of config.json")?;
+        // save_config leaves the credentials out
+        save_config(config, state_dir)?;
+        info!("Moved credentials from config.json to {}", store.name());
+        return Ok(());
+    }
+
+    if let Some(credentials) = store.load()? {
+        debug!("Loaded credentials from {}", store.name());
+        config.credentials = credentials;
+    }
+    Ok(())
+}
+
+// Credentials as far as they can be read without asking for a passphrase, for `miniover status`
+pub fn peek_credentials(config: &Config, config_dir: &Path) -> Result<Credentials> {
+    if !config.credentials.is_empty() {
+        return Ok(config.credentials.clone());
+    }
+    Ok(open(config, config_dir, false)?.load()?.unwrap_or_default())
+}
+
+// Forget the stored credentials wherever they are, on logout
+pub fn clear_credentials(config_dir: &Path) {
+    match KeyringStore::open() {
+        Ok(store) => {
+            if let Err(e) = store.delete() {
+                warn!("Failed to remove credentials from the keyring: {}", e);
+            }
+        }
+        Err(e) => debug!("No keyring to clear: {}", e),
+    }
+
+    let path = config_dir.join(ENCRYPTED_FILENAME);
+    match fs::remove_file(&path) {
+        Ok(()) => info!("Removed {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+    }
+}
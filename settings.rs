@@ -0,0 +1,279 @@
+use crate::messages::FeedCommand;
+use crate::toast;
+use crate::types::Preferences;
+use crate::utils::write_atomic;
+use anyhow::{anyhow, Context, Error, Result};
+use log::{debug, error, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use toml::{Table, Value};
+
+const SETTINGS_FILENAME: &str = "settings.toml";
+// Edits to these are applied while running
+const WATCHED_FILENAMES: [&str; 2] = [SETTINGS_FILENAME, "rules.json"];
+// Editors often write a file in several steps, wait for them to finish
+const RELOAD_DELAY: Duration = Duration::from_millis(300);
+const ENV_PREFIX: &str = "MINIOVER_";
+// Nested keys in variable names, MINIOVER_QUIET_HOURS__MIN_PRIORITY is quiet_hours.min_priority
+const ENV_SEPARATOR: &str = "__";
+
+const HEADER: &str = "\
+# Miniover settings, see the README for what they do.
+# Each one can be overridden with a MINIOVER_* environment variable or --set KEY=VALUE.
+
+";
+
+// A setting replaced from the environment or the command line. Later ones win, which gives
+// defaults < settings.toml < MINIOVER_* variables < --set.
+#[derive(Clone, Debug)]
+pub struct Override {
+    // Dotted path, e.g. quiet_hours.min_priority
+    pub key: String,
+    pub value: Value,
+    // The variable or flag it came from, for `config show --effective`
+    pub source: String,
+}
+
+impl Override {
+    // KEY=VALUE as given to --set
+    pub fn parse_arg(arg: &str) -> Result<Self> {
+        let (key, value) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected --set KEY=VALUE, got '{}'", arg))?;
+        Ok(Self {
+            key: key.trim().to_string(),
+            value: parse_value(value),
+            source: format!("--set {}", key.trim()),
+        })
+    }
+}
+
+pub fn settings_path(config_dir: &Path) -> PathBuf {
+    config_dir.join(SETTINGS_FILENAME)
+}
+
+// Write settings.toml on first run, carrying over the preferences an older config.json held
+pub fn create_if_missing(config_dir: &Path, legacy: Option<&Preferences>) -> Result<()> {
+    let path = settings_path(config_dir);
+    if path.exists() {
+        return Ok(());
+    }
+
+    let preferences = legacy.cloned().unwrap_or_default();
+    let contents = format!("{}{}", HEADER, toml::to_string_pretty(&preferences)?);
+    write_atomic(&path, contents.as_bytes())?;
+    match legacy {
+        Some(_) => info!("Moved preferences from config.json to {}", path.display()),
+        None => info!("Created {}", path.display()),
+    }
+    Ok(())
+}
+
+// The effective settings: settings.toml (or, until that exists, the preferences from an older
+// config.json) with the environment and then `overrides` applied on top
+pub fn load(config_dir: &Path, legacy: Option<&Preferences>, overrides: &[Override]) -> Result<Preferences> {
+    load_layers(config_dir, legacy, &with_env(overrides))
+}
+
+fn load_layers(config_dir: &Path, legacy: Option<&Preferences>, overrides: &[Override]) -> Result<Preferences> {
+    let path = settings_path(config_dir);
+    let mut table = match fs::read_to_string(&path) {
+        // Checked on its own first so mistakes in the file are reported with their line
+        Ok(text) => parse(&text).with_context(|| format!("Invalid {}", path.display()))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => match legacy {
+            Some(legacy) => Table::try_from(legacy)?,
+            None => Table::new(),
+        },
+        Err(e) => return Err(Error::new(e).context(format!("Failed to read {}", path.display()))),
+    };
+
+    if overrides.is_empty() {
+        return deserialize(Value::Table(table));
+    }
+    for setting in overrides {
+        set(&mut table, &setting.key, setting.value.clone()).with_context(|| format!("Invalid {}", setting.source))?;
+    }
+    deserialize(Value::Table(table)).context("Invalid setting override")
+}
+
+fn parse(text: &str) -> Result<Table> {
+    let mut unknown = Vec::new();
+    let _: Preferences = serde_ignored::deserialize(toml::Deserializer::new(text), |path| unknown.push(path.to_string()))?;
+    check_unknown(unknown)?;
+    Ok(text.parse()?)
+}
+
+fn deserialize(value: Value) -> Result<Preferences> {
+    let mut unknown = Vec::new();
+    let preferences = serde_ignored::deserialize(value, |path| unknown.push(path.to_string()))?;
+    check_unknown(unknown)?;
+    Ok(preferences)
+}
+
+// Typos would otherwise be silently ignored
+fn check_unknown(unknown: Vec<String>) -> Result<()> {
+    match unknown.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!("Unknown setting(s): {}", unknown.join(", "))),
+    }
+}
+
+// Replace the value at a dotted path, creating the tables on the way
+fn set(table: &mut Table, key: &str, value: Value) -> Result<()> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap_or_default();
+    if last.is_empty() || parts.iter().any(|part| part.is_empty()) {
+        return Err(anyhow!("Invalid setting name '{}'", key));
+    }
+
+    let mut table = table;
+    for part in parts {
+        table = match table.entry(part).or_insert_with(|| Value::Table(Table::new())) {
+            Value::Table(inner) => inner,
+            _ => return Err(anyhow!("{} is not a table", part)),
+        };
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+// TOML syntax where that works (numbers, booleans, arrays, inline tables), a plain string
+// otherwise. Dates and times stay strings since that is how miniover reads them.
+fn parse_value(text: &str) -> Value {
+    match format!("value = {}", text).parse::<Table>().ok().and_then(|mut table| table.remove("value")) {
+        Some(Value::Datetime(_)) | None => Value::String(text.to_string()),
+        Some(value) => value,
+    }
+}
+
+// MINIOVER_* variables naming a setting, sorted so a whole table is set before its keys.
+// Others, like MINIOVER_PASSPHRASE, are left alone.
+fn env_overrides() -> Vec<Override> {
+    let known = match serde_json::to_value(Preferences::default()) {
+        Ok(serde_json::Value::Object(defaults)) => defaults,
+        _ => return vec![],
+    };
+
+    let mut overrides: Vec<Override> = std::env::vars_os()
+        .filter_map(|(name, value)| {
+            let (name, value) = (name.into_string().ok()?, value.into_string().ok()?);
+            let key = name.strip_prefix(ENV_PREFIX)?.to_lowercase().replace(ENV_SEPARATOR, ".");
+            known.contains_key(key.split('.').next()?).then(|| Override {
+                key,
+                value: parse_value(&value),
+                source: name,
+            })
+        })
+        .collect();
+    overrides.sort_by(|a, b| a.source.cmp(&b.source));
+    overrides
+}
+
+fn with_env(overrides: &[Override]) -> Vec<Override> {
+    env_overrides().into_iter().chain(overrides.iter().cloned()).collect()
+}
+
+// `miniover config show [--effective]`
+pub fn show(config_dir: &Path, legacy: Option<&Preferences>, overrides: &[Override], effective: bool) -> Result<()> {
+    let overrides = match effective {
+        true => with_env(overrides),
+        false => vec![],
+    };
+    let preferences = load_layers(config_dir, legacy, &overrides)?;
+
+    println!("# {}", settings_path(config_dir).display());
+    if !overrides.is_empty() {
+        println!("# Overridden, later ones win:");
+    }
+    for setting in &overrides {
+        println!("# {} from {}", setting.key, setting.source);
+    }
+    print!("{}", toml::to_string_pretty(&preferences)?);
+    Ok(())
+}
+
+// Reload settings.toml and rules.json whenever they change and hand them to the feed
+pub fn spawn_watcher(config_dir: PathBuf, overrides: Vec<Override>, feed: mpsc::Sender<FeedCommand>, shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        if let Err(e) = watch(&config_dir, &overrides, feed, shutdown).await {
+            error!("Not watching {} for changes: {:#}", config_dir.display(), e);
+        }
+    });
+}
+
+async fn watch(config_dir: &Path, overrides: &[Override], feed: mpsc::Sender<FeedCommand>, shutdown: CancellationToken) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        tx.send(event).ok();
+    })?;
+    // The directory rather than the files, since editors tend to replace a file instead of writing to it
+    watcher.watch(config_dir, RecursiveMode::NonRecursive)?;
+    info!("Watching {} for settings changes", config_dir.display());
+
+    loop {
+        let event: notify::Result<notify::Event> = tokio::select! {
+            event = rx.recv() => match event {
+                Some(event) => event,
+                None => return Err(anyhow!("File watcher stopped")),
+            },
+            _ = shutdown.cancelled() => return Ok(()),
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("File watcher error: {}", e);
+                continue;
+            }
+        };
+        let relevant = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event.paths.iter().any(|path| {
+                path.file_name()
+                    .is_some_and(|name| WATCHED_FILENAMES.iter().any(|watched| name == *watched))
+            });
+        if !relevant {
+            continue;
+        }
+
+        time::sleep(RELOAD_DELAY).await;
+        while rx.try_recv().is_ok() {}
+
+        debug!("Settings changed on disk, reloading");
+        if !settings_path(config_dir).exists() {
+            warn!("{} is gone, keeping the current settings", settings_path(config_dir).display());
+            continue;
+        }
+        match load(config_dir, None, overrides) {
+            Ok(preferences) => {
+                if feed.send(FeedCommand::ApplySettings(Box::new(preferences))).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => report_invalid(&e),
+        }
+    }
+}
+
+// A bad edit is pointed out on the desktop, the running settings stay as they were
+pub fn report_invalid(error: &Error) {
+    error!("Invalid settings, keeping the previous ones: {:#}", error);
+    toast::show_error_notification("Invalid Settings", &format!("{:#}\n\nKeeping the previous settings.", error)).ok();
+}
+
+// Connections and the credential store are only set up on startup
+pub fn needs_restart(old: &Preferences, new: &Preferences) -> bool {
+    fn differs<T: Serialize>(old: &T, new: &T) -> bool {
+        serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+    }
+
+    differs(&old.endpoints, &new.endpoints)
+        || differs(&old.proxy, &new.proxy)
+        || differs(&old.tls, &new.tls)
+        || differs(&old.credentials, &new.credentials)
+}
@@ -0,0 +1,157 @@
+use crate::control;
+use crate::outbox::Outbox;
+use crate::quiet::Clock;
+use crate::secrets::{self, Credentials};
+use crate::settings::{self, Override};
+use crate::types::ConnectionStatus;
+use crate::utils::{app_paths, load_config, unix_now, write_atomic};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{fs, path::Path};
+
+pub const STATUS_FILENAME: &str = "status.json";
+
+// Snapshot of the running client, written by the feed task for `miniover status`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuntimeStatus {
+    pub pid: u32,
+    pub connection: ConnectionStatus,
+    pub updated_at: i64,
+}
+
+impl RuntimeStatus {
+    pub fn new(connection: ConnectionStatus) -> Self {
+        Self {
+            pid: std::process::id(),
+            connection,
+            updated_at: unix_now(),
+        }
+    }
+
+    pub fn load(state_dir: &Path) -> Result<Option<RuntimeStatus>> {
+        let status_path = state_dir.join(STATUS_FILENAME);
+
+        if !status_path.exists() {
+            return Ok(None);
+        }
+
+        let status_data = fs::read_to_string(status_path)?;
+        Ok(Some(serde_json::from_str(&status_data)?))
+    }
+
+    pub fn save(&self, state_dir: &Path) -> Result<()> {
+        let status_path = state_dir.join(STATUS_FILENAME);
+        let status_json = serde_json::to_string_pretty(self)?;
+
+        // `miniover status` may read it at any moment, never let it see a half-written file
+        write_atomic(&status_path, status_json.as_bytes())
+    }
+
+    // Called when the client stops; leaves a file another instance has taken over alone
+    pub fn remove(state_dir: &Path) -> Result<()> {
+        if Self::load(state_dir)?.is_some_and(|status| status.pid == std::process::id()) {
+            fs::remove_file(state_dir.join(STATUS_FILENAME))?;
+        }
+        Ok(())
+    }
+}
+
+// Pid of the instance answering on the control socket, if any
+fn running_pid() -> Option<u32> {
+    let reply = control::send_request(&json!({ "command": "status" })).ok()?;
+    reply["pid"].as_u64().and_then(|pid| u32::try_from(pid).ok())
+}
+
+// Print a summary of the on-disk state for `miniover status`
+pub fn print_status(overrides: &[Override], clock: &dyn Clock) -> Result<()> {
+    let paths = app_paths();
+    let mut config = load_config(&paths.state_dir)?;
+    config.preferences = settings::load(&paths.config_dir, config.legacy_preferences.as_ref(), overrides)?;
+    let outbox = Outbox::load(&paths.state_dir)?;
+    // An unreadable status file (e.g. from an older version) just means "unknown"
+    let runtime = RuntimeStatus::load(&paths.state_dir).ok().flatten();
+    // A crashed client leaves its file behind, so it only counts while that client still answers
+    let running = runtime.as_ref().is_some_and(|runtime| running_pid() == Some(runtime.pid));
+
+    println!("Miniover status");
+    println!("  Config directory: {}", paths.config_dir.display());
+    println!("  State directory:  {}", paths.state_dir.display());
+
+    match secrets::peek_credentials(&config, &paths.config_dir) {
+        Ok(Credentials { secret: Some(_), device_id: Some(device_id), .. }) => {
+            println!("  Logged in:        yes (device {})", device_id)
+        }
+        Ok(_) => println!("  Logged in:        no"),
+        Err(e) => println!("  Logged in:        unknown ({:#})", e),
+    }
+
+    let local_now = clock.now();
+    let now = local_now.timestamp();
+    match runtime {
+        Some(runtime) if running => {
+            let connection = &runtime.connection;
+            println!(
+                "  Connection:       {} (reported by pid {} {}s ago)",
+                connection.state,
+                runtime.pid,
+                (now - runtime.updated_at).max(0)
+            );
+            println!("  Transport:        {}", connection.transport);
+            if let Some(error) = &connection.last_error {
+                println!("  Last error:       {}", error);
+            }
+        }
+        Some(runtime) => println!("  Connection:       unknown (pid {} isn't running or answering)", runtime.pid),
+        None => println!("  Connection:       unknown (not running)"),
+    }
+
+    match &config.state.pause {
+        Some(pause) if pause.is_active(now) => {
+            println!("  Notifications:    {} ({} held)", pause.describe(&local_now).to_lowercase(), pause.held.len())
+        }
+        _ if config.preferences.quiet_hours.is_quiet(&local_now) => {
+            println!("  Notifications:    quiet hours ({} held)", config.state.quiet_held.len())
+        }
+        _ => println!("  Notifications:    on"),
+    }
+
+    println!("  Last message ID:  {}", config.state.last_message_id.as_deref().unwrap_or("none"));
+    println!("  Pending actions:  {}", outbox.entries.len());
+
+    for entry in &outbox.entries {
+        let retry = match entry.next_attempt_at - now {
+            secs if secs > 0 => format!("retry in {}s", secs),
+            _ => "retry due".to_string(),
+        };
+        println!("    - {} (attempts: {}, {})", entry.action, entry.attempts, retry);
+        if let Some(error) = &entry.last_error {
+            println!("      last error: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_only_its_own_status() {
+        let dir = tempfile::tempdir().unwrap();
+        RuntimeStatus::remove(dir.path()).unwrap();
+
+        RuntimeStatus::new(ConnectionStatus::default()).save(dir.path()).unwrap();
+        RuntimeStatus::remove(dir.path()).unwrap();
+        assert!(RuntimeStatus::load(dir.path()).unwrap().is_none());
+
+        let other = RuntimeStatus {
+            pid: 0,
+            ..RuntimeStatus::new(ConnectionStatus::default())
+        };
+        other.save(dir.path()).unwrap();
+        RuntimeStatus::remove(dir.path()).unwrap();
+        assert_eq!(RuntimeStatus::load(dir.path()).unwrap().unwrap().pid, 0);
+    }
+}
@@ -0,0 +1,258 @@
+#[cfg(unix)]
+use log::{debug, warn};
+use std::time::Duration;
+use tokio_util::sync::DropGuard;
+
+// Readiness, status and watchdog notifications for a Type=notify systemd unit.
+// Does nothing unless systemd started us with $NOTIFY_SOCKET set.
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: Option<std::os::unix::net::UnixDatagram>,
+    ready: bool,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        Self {
+            #[cfg(unix)]
+            socket: platform::connect(),
+            ready: false,
+        }
+    }
+
+    // Only the first call reaches systemd, later ones are ignored
+    pub fn ready(&mut self) {
+        if !self.ready {
+            self.ready = true;
+            self.send("READY=1");
+        }
+    }
+
+    // Shown by `systemctl --user status miniover`
+    pub fn status(&self, text: &str) {
+        // One line per assignment, a newline would start a new one
+        self.send(&format!("STATUS={}", text.replace('\n', " ")));
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    // Ping the watchdog at half the WatchdogSec systemd asked for until the guard is dropped,
+    // so a hung or crashed feed gets restarted whatever state the connection is in
+    pub fn start_watchdog(&self) -> Option<DropGuard> {
+        let interval = watchdog_interval(
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+        )?;
+        self.start_watchdog_every(interval)
+    }
+
+    #[cfg(unix)]
+    fn start_watchdog_every(&self, interval: Duration) -> Option<DropGuard> {
+        let socket = match self.socket.as_ref()?.try_clone() {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Failed to start the systemd watchdog: {}", e);
+                return None;
+            }
+        };
+        debug!("Pinging the systemd watchdog every {}s", interval.as_secs_f32());
+
+        let stop = tokio_util::sync::CancellationToken::new();
+        let stopped = stop.clone();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = timer.tick() => platform::send(&socket, "WATCHDOG=1"),
+                    _ = stopped.cancelled() => return,
+                }
+            }
+        });
+        Some(stop.drop_guard())
+    }
+
+    #[cfg(not(unix))]
+    fn start_watchdog_every(&self, _interval: Duration) -> Option<DropGuard> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn send(&self, state: &str) {
+        if let Some(socket) = &self.socket {
+            platform::send(socket, state);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _state: &str) {}
+}
+
+// WATCHDOG_USEC is meant for us unless WATCHDOG_PID names another process
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::{debug, warn};
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    pub fn connect() -> Option<UnixDatagram> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        connect_to(&path.to_string_lossy())
+    }
+
+    pub fn send(socket: &UnixDatagram, state: &str) {
+        if let Err(e) = socket.send(state.as_bytes()) {
+            warn!("Failed to notify systemd ({}): {}", state, e);
+        }
+    }
+
+    pub fn connect_to(path: &str) -> Option<UnixDatagram> {
+        let connected = address(path).and_then(|address| {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect_addr(&address)?;
+            Ok(socket)
+        });
+        match connected {
+            Ok(socket) => {
+                debug!("Sending systemd notifications to {}", path);
+                Some(socket)
+            }
+            Err(e) => {
+                warn!("Failed to connect to NOTIFY_SOCKET {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    // A leading '@' names a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    fn address(path: &str) -> std::io::Result<SocketAddr> {
+        use std::os::linux::net::SocketAddrExt;
+        match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(path),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn address(path: &str) -> std::io::Result<SocketAddr> {
+        SocketAddr::from_pathname(path)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    // Stands in for systemd, returning the next datagram or None once they stop
+    fn listen(socket: UnixDatagram) -> impl FnMut() -> Option<String> {
+        socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        move || {
+            let mut buffer = [0; 256];
+            let length = socket.recv(&mut buffer).ok()?;
+            Some(String::from_utf8_lossy(&buffer[..length]).into_owned())
+        }
+    }
+
+    fn notify_all(notifier: &mut Notifier) {
+        notifier.ready();
+        notifier.ready();
+        notifier.status("Connected\n3 unread");
+        notifier.stopping();
+    }
+
+    #[test]
+    fn sends_notifications_to_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let mut received = listen(UnixDatagram::bind(&path).unwrap());
+
+        let mut notifier = Notifier {
+            socket: platform::connect_to(path.to_str().unwrap()),
+            ready: false,
+        };
+        notify_all(&mut notifier);
+
+        // READY=1 only once, and the status stays on one line
+        for expected in ["READY=1", "STATUS=Connected 3 unread", "STOPPING=1"] {
+            assert_eq!(received().as_deref(), Some(expected));
+        }
+        assert_eq!(received(), None);
+    }
+
+    // The pinger needs a worker thread while the test blocks on the socket
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn pings_the_watchdog_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let mut received = listen(UnixDatagram::bind(&path).unwrap());
+
+        let notifier = Notifier {
+            socket: platform::connect_to(path.to_str().unwrap()),
+            ready: false,
+        };
+        let watchdog = notifier.start_watchdog_every(Duration::from_millis(20)).unwrap();
+        for _ in 0..3 {
+            assert_eq!(received().as_deref(), Some("WATCHDOG=1"));
+        }
+
+        drop(watchdog);
+        let mut late = 0;
+        while received().is_some() {
+            late += 1;
+            assert!(late < 3, "still pinging");
+        }
+    }
+
+    #[test]
+    fn reads_the_watchdog_interval() {
+        let pid = std::process::id().to_string();
+        let cases = [
+            (Some("120000000"), None, Some(Duration::from_secs(60))),
+            (Some("120000000"), Some(pid.as_str()), Some(Duration::from_secs(60))),
+            (Some("120000000"), Some("0"), None),
+            (Some("0"), None, None),
+            (Some("2m"), None, None),
+            (None, None, None),
+        ];
+        for (usec, pid, expected) in cases {
+            assert_eq!(watchdog_interval(usec, pid), expected, "{:?} {:?}", usec, pid);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn connects_to_abstract_sockets() {
+        use std::os::linux::net::SocketAddrExt;
+        let name = format!("miniover-test-notify-{}", std::process::id());
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let mut received = listen(UnixDatagram::bind_addr(&address).unwrap());
+
+        let mut notifier = Notifier {
+            socket: platform::connect_to(&format!("@{}", name)),
+            ready: false,
+        };
+        notifier.ready();
+        assert_eq!(received().as_deref(), Some("READY=1"));
+    }
+
+    #[test]
+    fn does_nothing_without_a_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = platform::connect_to(dir.path().join("missing").to_str().unwrap());
+        assert!(socket.is_none());
+
+        let mut notifier = Notifier { socket, ready: false };
+        notify_all(&mut notifier);
+        assert!(notifier.ready);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::icons;
+use crate::types::{ConnectionState, Event};
+use anyhow::Result;
+use std::sync::mpsc::Sender;
+
+pub use platform::SysTray;
+
+// Platform independent description of the tray menu, rebuilt whenever it changes
+#[derive(Clone, Debug)]
+pub enum MenuEntry {
+    Item { label: String, event: Event },
+    // Disabled text, e.g. a placeholder in an empty submenu
+    Label(String),
+    Submenu { label: String, entries: Vec<MenuEntry> },
+    Separator,
+}
+
+impl MenuEntry {
+    pub fn item(label: impl Into<String>, event: Event) -> Self {
+        MenuEntry::Item { label: label.into(), event }
+    }
+}
+
+// ============================================================================
+// Linux implementation using ksni (StatusNotifierItem)
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{icons, ConnectionState, Event, MenuEntry, Result, Sender};
+    use ksni::{menu::{StandardItem, SubMenu}, Handle, Icon, ToolTip};
+    use log::{debug, error};
+
+    struct KsniTray {
+        title: String,
+        icon: Vec<Icon>,
+        tooltip: ToolTip,
+        menu: Vec<MenuEntry>,
+        events: Sender<Event>,
+    }
+
+    impl ksni::Tray for KsniTray {
+        fn id(&self) -> String {
+            self.title.clone()
+        }
+
+        fn title(&self) -> String {
+            self.title.clone()
+        }
+
+        fn icon_name(&self) -> String {
+            // Fall back to a common system icon if the embedded one couldn't be decoded
+            match self.icon.is_empty() {
+                true => "dialog-information".to_string(),
+                false => String::new(),
+            }
+        }
+
+        fn icon_pixmap(&self) -> Vec<Icon> {
+            self.icon.clone()
+        }
+
+        fn tool_tip(&self) -> ToolTip {
+            self.tooltip.clone()
+        }
+
+        fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+            self.menu.iter().map(to_ksni).collect()
+        }
+    }
+
+    fn to_ksni(entry: &MenuEntry) -> ksni::MenuItem<KsniTray> {
+        match entry {
+            MenuEntry::Item { label, event } => {
+                let event = event.clone();
+                StandardItem {
+                    label: label.clone(),
+                    activate: Box::new(move |tray: &mut KsniTray| {
+                        if let Err(e) = tray.events.send(event.clone()) {
+                            error!("Failed to send menu event: {:?}", e);
+                        }
+                    }),
+                    ..Default::default()
+                }
+                .into()
+            }
+            MenuEntry::Label(label) => StandardItem {
+                label: label.clone(),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+            MenuEntry::Submenu { label, entries } => SubMenu {
+                label: label.clone(),
+                submenu: entries.iter().map(to_ksni).collect(),
+                ..Default::default()
+            }
+            .into(),
+            MenuEntry::Separator => ksni::MenuItem::Separator,
+        }
+    }
+
+    pub struct SysTray {
+        handle: Handle<KsniTray>,
+    }
+
+    impl SysTray {
+        pub fn new(title: &str, events: Sender<Event>) -> Result<Self> {
+            let service = ksni::TrayService::new(KsniTray {
+                title: title.to_string(),
+                icon: render_icon(ConnectionState::Connecting),
+                tooltip: ToolTip {
+                    title: title.to_string(),
+                    ..Default::default()
+                },
+                menu: vec![],
+                events,
+            });
+
+            let handle = service.handle();
+            service.spawn();
+
+            Ok(Self { handle })
+        }
+
+        pub fn set_menu(&mut self, menu: Vec<MenuEntry>) -> Result<()> {
+            self.handle.update(move |tray| tray.menu = menu);
+            Ok(())
+        }
+
+        pub fn set_icon_state(&mut self, state: ConnectionState) -> Result<()> {
+            let icon = render_icon(state);
+            self.handle.update(move |tray| tray.icon = icon);
+            Ok(())
+        }
+
+        pub fn set_tooltip(&mut self, title: &str, description: &str) -> Result<()> {
+            let (title, description) = (title.to_string(), description.to_string());
+            self.handle.update(move |tray| {
+                tray.tooltip.title = title;
+                tray.tooltip.description = description;
+            });
+            Ok(())
+        }
+    }
+
+    fn render_icon(state: ConnectionState) -> Vec<Icon> {
+        let Some(image) = icons::render(state) else {
+            return vec![];
+        };
+
+        let (width, height) = image.dimensions();
+        debug!("Tray icon rendered for {:?}: {}x{}", state, width, height);
+
+        // StatusNotifierItem wants ARGB32 in network byte order
+        let data = image
+            .pixels()
+            .flat_map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                [a, r, g, b]
+            })
+            .collect();
+
+        vec![Icon {
+            width: width as i32,
+            height: height as i32,
+            data,
+        }]
+    }
+}
+
+// ============================================================================
+// Windows implementation using tray-item
+// ============================================================================
+
+#[cfg(windows)]
+mod platform {
+    use super::{icons, ConnectionState, Event, MenuEntry, Result, Sender};
+    use log::{debug, error};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tray_item::{IconSource, TrayItem};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{CreateIcon, HICON};
+
+    // Windows limits tray tooltips to 127 characters
+    const MAX_TOOLTIP_CHARS: usize = 127;
+
+    // tray-item has no submenus and can't remove entries, so the menu is
+    // flattened into rows: submenus become a header followed by indented items
+    #[derive(Clone, Copy, PartialEq)]
+    enum RowKind {
+        Item,
+        Label,
+        Separator,
+    }
+
+    struct Row {
+        kind: RowKind,
+        label: String,
+        event: Option<Event>,
+    }
+
+    fn flatten(entries: &[MenuEntry], indent: &str, rows: &mut Vec<Row>) {
+        for entry in entries {
+            match entry {
+                MenuEntry::Item { label, event } => rows.push(Row {
+                    kind: RowKind::Item,
+                    label: format!("{}{}", indent, label),
+                    event: Some(event.clone()),
+                }),
+                MenuEntry::Label(label) => rows.push(Row {
+                    kind: RowKind::Label,
+                    label: format!("{}{}", indent, label),
+                    event: None,
+                }),
+                MenuEntry::Submenu { label, entries } => {
+                    rows.push(Row {
+                        kind: RowKind::Label,
+                        label: format!("{}{}", indent, label),
+                        event: None,
+                    });
+                    flatten(entries, &format!("{}    ", indent), rows);
+                }
+                MenuEntry::Separator => rows.push(Row {
+                    kind: RowKind::Separator,
+                    label: String::new(),
+                    event: None,
+                }),
+            }
+        }
+    }
+
+    pub struct SysTray {
+        tray: TrayItem,
+        title: String,
+        events: Sender<Event>,
+        // Menu ids and kinds of the rows currently in the menu
+        rows: Vec<(RowKind, u32)>,
+        // What each row sends when clicked, swapped in place when labels change
+        actions: Arc<Mutex<Vec<Option<Event>>>>,
+        state: ConnectionState,
+        tooltip: String,
+        // Generated icons are kept for the lifetime of the tray
+        icons: HashMap<ConnectionState, HICON>,
+    }
+
+    impl SysTray {
+        pub fn new(title: &str, events: Sender<Event>) -> Result<Self> {
+            let tray = TrayItem::new(title, IconSource::Resource("app-icon"))?;
+            let mut tray = Self {
+                tray,
+                title: title.to_string(),
+                events,
+                rows: vec![],
+                actions: Arc::new(Mutex::new(vec![])),
+                state: ConnectionState::Connecting,
+                tooltip: title.to_string(),
+                icons: HashMap::new(),
+            };
+            tray.set_icon_state(ConnectionState::Connecting)?;
+            Ok(tray)
+        }
+
+        pub fn set_menu(&mut self, menu: Vec<MenuEntry>) -> Result<()> {
+            let mut rows = Vec::new();
+            flatten(&menu, "", &mut rows);
+
+            let same_shape = rows.len() == self.rows.len()
+                && rows.iter().zip(&self.rows).all(|(row, (kind, _))| row.kind == *kind);
+
+            if same_shape {
+                for (row, (kind, id)) in rows.iter().zip(&self.rows) {
+                    match kind {
+                        RowKind::Item => self.tray.inner_mut().set_menu_item_label(&row.label, *id)?,
+                        RowKind::Label => self.tray.inner_mut().set_label(&row.label, *id)?,
+                        RowKind::Separator => {}
+                    }
+                }
+            } else {
+                if !self.rows.is_empty() {
+                    // Entries can't be removed, so start over with a fresh tray icon
+                    debug!("Tray menu layout changed, recreating tray icon");
+                    self.tray = TrayItem::new(&self.title, IconSource::Resource("app-icon"))?;
+                    self.rows.clear();
+                    self.set_icon_state(self.state)?;
+                    self.tray.inner_mut().set_tooltip(&self.tooltip)?;
+                }
+
+                for (index, row) in rows.iter().enumerate() {
+                    let id = match row.kind {
+                        RowKind::Item => {
+                            let (actions, events) = (self.actions.clone(), self.events.clone());
+                            self.tray.inner_mut().add_menu_item_with_id(&row.label, move || {
+                                let event = actions.lock().unwrap().get(index).cloned().flatten();
+                                if let Some(event) = event && let Err(e) = events.send(event) {
+                                    error!("Failed to send menu event: {:?}", e);
+                                }
+                            })?
+                        }
+                        RowKind::Label => self.tray.inner_mut().add_label_with_id(&row.label)?,
+                        RowKind::Separator => self.tray.inner_mut().add_separator_with_id()?,
+                    };
+                    self.rows.push((row.kind, id));
+                }
+            }
+
+            *self.actions.lock().unwrap() = rows.into_iter().map(|row| row.event).collect();
+            Ok(())
+        }
+
+        pub fn set_icon_state(&mut self, state: ConnectionState) -> Result<()> {
+            self.state = state;
+
+            // The resource icon has all the sizes Windows wants, use it when we can
+            if state == ConnectionState::Connected {
+                self.tray.set_icon(IconSource::Resource("app-icon"))?;
+                return Ok(());
+            }
+
+            let icon = match self.icons.get(&state) {
+                Some(icon) => *icon,
+                None => match create_icon(state) {
+                    Some(icon) => *self.icons.entry(state).or_insert(icon),
+                    None => return Ok(()),
+                },
+            };
+
+            self.tray.set_icon(IconSource::RawIcon(icon))?;
+            Ok(())
+        }
+
+        pub fn set_tooltip(&mut self, title: &str, description: &str) -> Result<()> {
+            self.tooltip = format!("{}\n{}", title, description)
+                .trim_end()
+                .chars()
+                .take(MAX_TOOLTIP_CHARS)
+                .collect();
+            self.tray.inner_mut().set_tooltip(&self.tooltip)?;
+            Ok(())
+        }
+    }
+
+    fn create_icon(state: ConnectionState) -> Option<HICON> {
+        let image = icons::render(state)?;
+        let (width, height) = image.dimensions();
+
+        // 32bpp BGRA colour bits; alpha makes the AND mask irrelevant, so leave it empty
+        let xor_bits: Vec<u8> = image
+            .pixels()
+            .flat_map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                [b, g, r, a]
+            })
+            .collect();
+        let and_bits = vec![0u8; width.div_ceil(16) as usize * 2 * height as usize];
+
+        let icon = unsafe {
+            CreateIcon(0, width as i32, height as i32, 1, 32, and_bits.as_ptr(), xor_bits.as_ptr())
+        };
+
+        if icon == 0 {
+            error!("Failed to create tray icon for {:?}", state);
+            return None;
+        }
+        Some(icon)
+    }
+}
@@ -1,8 +1,40 @@
 use anyhow::Result;
 use log::error;
+use serde::Deserialize;
 
 use crate::types::Message;
 
+// Per-notification tweaks on top of what the message itself asks for
+#[derive(Clone, Debug, Default)]
+pub struct NotifyOptions {
+    // No sound, e.g. during quiet hours
+    pub silent: bool,
+    // Overrides the urgency derived from the message priority
+    pub urgency: Option<UrgencyLevel>,
+    // Stay on screen until dismissed (as far as the platform allows)
+    pub sticky: bool,
+    // Platform sound name, e.g. "message-new-instant" (Linux) or "Reminder" (Windows)
+    pub sound: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrgencyLevel {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl UrgencyLevel {
+    fn for_message(message: &Message, options: &NotifyOptions) -> Self {
+        options.urgency.unwrap_or(match message.priority {
+            p if p >= 2 => UrgencyLevel::Critical,
+            p if p >= 1 => UrgencyLevel::Normal,
+            _ => UrgencyLevel::Low,
+        })
+    }
+}
+
 // ============================================================================
 // Windows implementation using tauri-winrt-notification
 // ============================================================================
@@ -11,7 +43,7 @@ use crate::types::Message;
 use tauri_winrt_notification::{Duration, Sound, Toast};
 
 #[cfg(windows)]
-pub fn show_notification(message: &Message) -> Result<()> {
+pub fn show_notification(message: &Message, options: &NotifyOptions) -> Result<()> {
     let title = match &message.title {
         Some(title) if !title.is_empty() => title,
         _ => &message.app
@@ -22,13 +54,26 @@ pub fn show_notification(message: &Message) -> Result<()> {
         _ => None,
     };
 
+    let urgency = UrgencyLevel::for_message(message, options);
+    let duration = match options.sticky || urgency == UrgencyLevel::Critical {
+        true => Duration::Long,
+        false => Duration::Short,
+    };
+
     let mut notification = Toast::new(Toast::POWERSHELL_APP_ID) 
         .title(title)
         .text1(&message.message)
-        .duration(Duration::Short);
+        .duration(duration);
 
     // Set sound based on message.sound if available
-    if message.priority >= 1 {
+    if options.silent {
+        notification = notification.sound(None);
+    } else if let Some(name) = &options.sound {
+        match name.parse::<Sound>() {
+            Ok(sound) => notification = notification.sound(Some(sound)),
+            Err(_) => error!("Unknown notification sound: {}", name),
+        }
+    } else if urgency != UrgencyLevel::Low {
         notification = notification.sound(Some(Sound::SMS));
     }
 
@@ -79,21 +124,19 @@ pub fn show_success_notification(title: &str, message: &str) -> Result<()> {
 // ============================================================================
 
 #[cfg(target_os = "linux")]
-use notify_rust::{Notification, Urgency};
+use notify_rust::{Hint, Notification, Timeout, Urgency};
 
 #[cfg(target_os = "linux")]
-pub fn show_notification(message: &Message) -> Result<()> {
+pub fn show_notification(message: &Message, options: &NotifyOptions) -> Result<()> {
     let title = match &message.title {
         Some(title) if !title.is_empty() => title,
         _ => &message.app
     };
 
-    let urgency = if message.priority >= 2 {
-        Urgency::Critical
-    } else if message.priority >= 1 {
-        Urgency::Normal
-    } else {
-        Urgency::Low
+    let urgency = match UrgencyLevel::for_message(message, options) {
+        UrgencyLevel::Critical => Urgency::Critical,
+        UrgencyLevel::Normal => Urgency::Normal,
+        UrgencyLevel::Low => Urgency::Low,
     };
 
     let mut notification = Notification::new();
@@ -103,6 +146,16 @@ pub fn show_notification(message: &Message) -> Result<()> {
         .appname("Miniover")
         .urgency(urgency);
 
+    if options.silent {
+        notification.hint(Hint::SuppressSound(true));
+    } else if let Some(sound) = &options.sound {
+        notification.hint(Hint::SoundName(sound.clone()));
+    }
+
+    if options.sticky {
+        notification.hint(Hint::Resident(true)).timeout(Timeout::Never);
+    }
+
     // Add click action if URL is available
     if let Some(url) = &message.url {
         if !url.is_empty() {
@@ -1,40 +1,245 @@
-use crate::types::{Event, AppState};
-use crate::toast;
-use crate::utils::{get_app_config_dir, get_app_paths, save_config, toggle_autorun};
-use anyhow::Result;
+use crate::types::{Event, AppState, Config, ConnectionStatus, Message};
+use crate::messages::FeedCommand;
+use crate::pause::{self, PauseDuration, PauseState};
+use crate::quiet::{Clock, SystemClock};
+use crate::secrets;
+use crate::systray::{MenuEntry, SysTray};
+use crate::toast::{self, NotifyOptions};
+use crate::utils::{app_paths, save_config, toggle_autorun};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, TimeZone};
 use log::{error, info, debug};
-use tokio::sync::{mpsc, Mutex};
-use tray_item::TrayItem;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use std::collections::VecDeque;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+
+// How often to check whether a timed pause or quiet hours have run out; a plain
+// timer would fire late after a suspend
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+// Messages held by quiet hours are shown one by one up to this many, summarised beyond
+const MAX_RELEASED_NOTIFICATIONS: usize = 3;
+
+// Longest menu label for a recent message before it gets cut off
+const MAX_RECENT_LABEL_CHARS: usize = 60;
 
 pub struct TrayContext {
-    pub tray: TrayItem,
-    pub toggle_startup_menu_item_id: u32,
+    pub tray: SysTray,
+    clock: Box<dyn Clock>,
+}
+
+impl TrayContext {
+    pub fn new(tray: SysTray) -> Self {
+        Self { tray, clock: Box::new(SystemClock) }
+    }
+    
+    // Rebuild the whole menu from the current config and recent messages
+    fn refresh_menu(&mut self, state: &AppState) {
+        let menu = build_menu(&state.config, &state.recent, &self.clock.now());
+        if let Err(e) = self.tray.set_menu(menu) {
+            error!("Failed to update tray menu: {}", e);
+        }
+    }
+}
+
+fn build_menu(config: &Config, recent: &VecDeque<Message>, now: &DateTime<Local>) -> Vec<MenuEntry> {
+    let recent_entries = match recent.is_empty() {
+        true => vec![MenuEntry::Label("No messages yet".to_string())],
+        false => recent
+            .iter()
+            .map(|message| MenuEntry::item(recent_label(message), Event::ShowRecentMessage(message.id)))
+            .collect(),
+    };
+    
+    let toggle_text = match config.state.start_on_boot {
+        true => "Start on boot [✓]",
+        false => "Start on boot [ ]",
+    };
+    
+    let mut menu = vec![
+        MenuEntry::Submenu {
+            label: "Recent messages".to_string(),
+            entries: recent_entries,
+        },
+        MenuEntry::Separator,
+    ];
+    
+    match &config.state.pause {
+        Some(pause) => {
+            menu.push(MenuEntry::Label(pause.describe(now)));
+            menu.push(MenuEntry::item("Resume notifications", Event::Resume));
+        }
+        None => menu.push(MenuEntry::Submenu {
+            label: "Pause notifications".to_string(),
+            entries: PauseDuration::ALL
+                .iter()
+                .map(|duration| MenuEntry::item(duration.label(), Event::Pause(*duration)))
+                .collect(),
+        }),
+    }
+    
+    menu.extend([
+        MenuEntry::item(toggle_text, Event::ToggleStartOnBoot),
+        MenuEntry::Separator,
+        MenuEntry::item("Show Logs", Event::ShowLogs),
+        MenuEntry::item("About", Event::ShowAbout),
+        MenuEntry::item("Quit", Event::Quit),
+        MenuEntry::item("Logout", Event::Logout),
+    ]);
+    menu
+}
+
+// Show what quiet hours held back once they are over (and we're not paused)
+fn release_quiet_held(config: &mut Config, state_dir: &Path, clock: &dyn Clock) {
+    if config.state.quiet_held.is_empty() || config.state.pause.is_some() || config.preferences.quiet_hours.is_quiet(&clock.now()) {
+        return;
+    }
+    
+    let held = std::mem::take(&mut config.state.quiet_held);
+    info!("Quiet hours over, showing {} held message(s)", held.len());
+    if let Err(e) = save_config(config, state_dir) {
+        error!("Failed to save config: {}", e);
+    }
+    
+    if held.len() <= MAX_RELEASED_NOTIFICATIONS {
+        for message in &held {
+            if let Err(e) = toast::show_notification(message, &NotifyOptions::default()) {
+                error!("Failed to show notification: {}", e);
+            }
+        }
+    } else if let Some(summary) = pause::summarize(held.iter().map(|message| message.app.as_str()), "during quiet hours") {
+        toast::show_success_notification("Quiet hours over", &summary).ok();
+    }
+}
+
+// Clear the pause and tell the user what they missed
+fn end_pause(config: &mut Config, state_dir: &Path) {
+    let Some(pause) = config.state.pause.take() else {
+        return;
+    };
+    
+    info!("Resuming notifications, {} message(s) held", pause.held.len());
+    if let Err(e) = save_config(config, state_dir) {
+        error!("Failed to save config: {}", e);
+    }
+    
+    let summary = pause.summary().unwrap_or_else(|| "No messages arrived while paused".to_string());
+    toast::show_success_notification("Notifications resumed", &summary).ok();
+}
+
+// "App: title", falling back to the first line of the message body
+fn recent_label(message: &Message) -> String {
+    let text = match &message.title {
+        Some(title) if !title.is_empty() => title.as_str(),
+        _ => message.message.lines().next().unwrap_or_default(),
+    };
+    
+    let label = format!("{}: {}", message.app, text);
+    match label.chars().count() > MAX_RECENT_LABEL_CHARS {
+        true => format!("{}…", label.chars().take(MAX_RECENT_LABEL_CHARS - 1).collect::<String>()),
+        false => label,
+    }
+}
+
+// Open the message's URL if it has one, otherwise show the notification again
+fn show_recent_message(message: &Message) {
+    if let Some(url) = message.url.as_deref().filter(|url| !url.is_empty()) {
+        if let Err(e) = open::that(url) {
+            error!("Failed to open URL: {}", e);
+        }
+        return;
+    }
+    
+    if let Err(e) = toast::show_notification(message, &NotifyOptions::default()) {
+        error!("Failed to show notification: {}", e);
+    }
+}
+
+// Reflect the feed's connection state in the tray icon and tooltip
+fn show_connection_status(tray: &mut SysTray, status: &ConnectionStatus) {
+    let mut description = Vec::new();
+    
+    match status.last_connected.and_then(|ts| Local.timestamp_opt(ts, 0).single()) {
+        Some(time) => description.push(format!("Last connected: {}", time.format("%Y-%m-%d %H:%M"))),
+        None => description.push("Not connected yet".to_string()),
+    }
+    if let Some(error) = &status.last_error {
+        description.push(format!("Last error: {}", error));
+    }
+    
+    if let Err(e) = tray.set_icon_state(status.state) {
+        error!("Failed to update tray icon: {}", e);
+    }
+    if let Err(e) = tray.set_tooltip(&format!("Miniover - {}", status.state), &description.join("\n")) {
+        error!("Failed to update tray tooltip: {}", e);
+    }
 }
 
 // Main function to consume tray events
-pub async fn consume_tray_events(mut rx: mpsc::Receiver<Event>, app_state: Arc<Mutex<AppState>>, mut tray_context: TrayContext) -> Result<()> {
-    let config_dir = get_app_config_dir();
+pub async fn consume_tray_events(
+    mut rx: mpsc::Receiver<Event>,
+    mut status_rx: watch::Receiver<ConnectionStatus>,
+    app_state: Arc<Mutex<AppState>>,
+    feed: mpsc::Sender<FeedCommand>,
+    mut tray_context: TrayContext,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let paths = app_paths();
 
     debug!("Tray events consumer started");
+    show_connection_status(&mut tray_context.tray, &status_rx.borrow_and_update());
+    tray_context.refresh_menu(&*app_state.lock().await);
     
-    while let Some(message) = rx.recv().await {
+    let mut pause_check = time::interval(PAUSE_CHECK_INTERVAL);
+    
+    loop {
+        let message = tokio::select! {
+            message = rx.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            Ok(()) = status_rx.changed() => {
+                let status = status_rx.borrow_and_update().clone();
+                debug!("Connection state changed: {:?}", status.state);
+                show_connection_status(&mut tray_context.tray, &status);
+                continue;
+            }
+            _ = pause_check.tick() => {
+                let mut state = app_state.lock().await;
+                let now = tray_context.clock.now().timestamp();
+                if state.config.state.pause.as_ref().is_some_and(|pause| !pause.is_active(now)) {
+                    end_pause(&mut state.config, &paths.state_dir);
+                    tray_context.refresh_menu(&state);
+                }
+                release_quiet_held(&mut state.config, &paths.state_dir, &*tray_context.clock);
+                continue;
+            }
+            _ = shutdown.cancelled() => {
+                debug!("Tray events consumer stopped");
+                return Ok(());
+            }
+        };
+        
         debug!("Received event on tray thread: {:?}", message);
         match message {
             Event::Quit => {
                 info!("Quitting application");
-                toast::show_success_notification("Miniover", "Miniover has been closed successfully")?;
-                std::process::exit(0);
+                toast::show_success_notification("Miniover", "Miniover has been closed successfully").ok();
+                shutdown.cancel();
+                return Ok(());
             }
             Event::ToggleStartOnBoot => {
                 info!("Toggling start on boot");
                 let app_state_clone_inner = app_state.clone();
-                let config_dir_clone = config_dir.clone();
+                let state_dir_clone = paths.state_dir.clone();
                 
                 let mut state = app_state_clone_inner.lock().await;
-                state.config.start_on_boot = !state.config.start_on_boot;
+                state.config.state.start_on_boot = !state.config.state.start_on_boot;
                 
-                if let Err(e) = save_config(&state.config, &config_dir_clone) {
+                if let Err(e) = save_config(&state.config, &state_dir_clone) {
                     error!("Failed to save config: {}", e);
                 }
                 
@@ -43,16 +248,51 @@ pub async fn consume_tray_events(mut rx: mpsc::Receiver<Event>, app_state: Arc<M
                 }
 
                 // update tray menu item state
-                let toggle_text = match state.config.start_on_boot {
-                    true => "Start on boot [✓]",
-                    false => "Start on boot [ ]",
-                };
-                tray_context.tray.inner_mut().set_menu_item_label(toggle_text, tray_context.toggle_startup_menu_item_id).unwrap();
+                tray_context.refresh_menu(&state);
 
                 // Notify user
-                let status = if state.config.start_on_boot { "enabled" } else { "disabled" };
+                let status = if state.config.state.start_on_boot { "enabled" } else { "disabled" };
                 toast::show_success_notification("Autostart Updated", &format!("Start on boot {}", status)).ok();
             }
+            Event::MessagesDelivered => {
+                tray_context.refresh_menu(&*app_state.lock().await);
+            }
+            Event::ShowRecentMessage(id) => {
+                match app_state.lock().await.recent.iter().find(|message| message.id == id) {
+                    Some(message) => show_recent_message(message),
+                    None => debug!("Recent message {} is no longer in the menu", id),
+                }
+            }
+            Event::Pause(duration) => {
+                let mut state = app_state.lock().await;
+                
+                // Extending a pause keeps what was already held for the summary
+                let held = state.config.state.pause.take().map(|pause| pause.held).unwrap_or_default();
+                let now = tray_context.clock.now();
+                let pause = PauseState { held, ..PauseState::new(duration, now) };
+                info!("Pausing notifications: {}", pause.describe(&now));
+                state.config.state.pause = Some(pause);
+                
+                if let Err(e) = save_config(&state.config, &paths.state_dir) {
+                    error!("Failed to save config: {}", e);
+                }
+                tray_context.refresh_menu(&state);
+            }
+            Event::Resume => {
+                let mut state = app_state.lock().await;
+                end_pause(&mut state.config, &paths.state_dir);
+                tray_context.refresh_menu(&state);
+            }
+            Event::Acknowledge(receipt) => {
+                if let Err(e) = feed.send(FeedCommand::Acknowledge(receipt)).await {
+                    error!("Failed to pass acknowledgement to the message feed: {}", e);
+                }
+            }
+            Event::Reconnect => {
+                if let Err(e) = feed.send(FeedCommand::Reconnect).await {
+                    error!("Failed to ask the message feed to reconnect: {}", e);
+                }
+            }
             Event::ShowAbout => {
                 toast::show_success_notification(
                     "About Miniover",
@@ -62,15 +302,16 @@ pub async fn consume_tray_events(mut rx: mpsc::Receiver<Event>, app_state: Arc<M
             Event::Logout => {
                 info!("Logging out");
                 let app_state_clone_inner = app_state.clone();
-                let config_dir_clone = config_dir.clone();
+                let config_dir_clone = paths.config_dir.clone();
                 
                 let mut state = app_state_clone_inner.lock().await;
                 // Clear credentials
-                state.config.user_key = None;
-                state.config.secret = None;
-                state.config.device_id = None;
+                state.config.credentials.user_key = None;
+                state.config.credentials.secret = None;
+                state.config.credentials.device_id = None;
+                secrets::clear_credentials(&config_dir_clone);
                 
-                if let Err(e) = save_config(&state.config, &config_dir_clone) {
+                if let Err(e) = save_config(&state.config, &paths.state_dir) {
                     error!("Failed to save config during logout: {}", e);
                 }
                 
@@ -78,12 +319,13 @@ pub async fn consume_tray_events(mut rx: mpsc::Receiver<Event>, app_state: Arc<M
                 toast::show_success_notification("Logged Out", "You have been logged out of Pushover").ok();
                 
                 // App should restart or show login screen
-                // For simplicity, just exit and let the user restart
-                std::process::exit(0);
+                // For simplicity, just quit and let the user restart
+                shutdown.cancel();
+                return Ok(());
             }
             Event::ShowLogs => {
                 info!("Showing logs");
-                let logs_dir = get_app_paths().1;
+                let logs_dir = paths.log_dir.clone();
                 info!("Logs directory: {:?}", logs_dir);
                 
                 // Ensure the directory exists
@@ -124,6 +366,5 @@ pub async fn consume_tray_events(mut rx: mpsc::Receiver<Event>, app_state: Arc<M
         }
     }
 
-    error!("Tray event channel closed unexpectedly");
-    Ok(())
+    Err(anyhow!("Tray event channel closed"))
 }
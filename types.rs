@@ -1,27 +1,227 @@
+use crate::hooks::HookConfig;
+use crate::mqtt::MqttConfig;
+use crate::net::EndpointsConfig;
+use crate::pause::{PauseDuration, PauseState};
+use crate::quiet::QuietHoursConfig;
+use crate::migrate::SCHEMA_VERSION;
+use crate::secrets::{Credentials, CredentialsConfig};
+use crate::webhook::WebhookConfig;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub struct AppState {
     pub config: Config,
+    // Messages shown recently, newest first, capped at preferences.recent_messages
+    pub recent: VecDeque<Message>,
 }
 
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            recent: VecDeque::new(),
+        }
+    }
+}
+
+// state.json in the state directory; older layouts are upgraded by migrate.rs when it's read
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
-    pub user_key: Option<String>,
-    pub secret: Option<String>,
-    pub device_id: Option<String>,
+    pub schema_version: u32,
+    // Kept in the keyring (see secrets.rs); still read here to migrate older plaintext configs
+    #[serde(skip_serializing)]
+    pub credentials: Credentials,
+    // Read from settings.toml (see settings.rs), never stored here
+    #[serde(skip)]
+    pub preferences: Preferences,
+    // Where schema version 2 kept the preferences; copied to settings.toml when that's created
+    #[serde(rename = "preferences", skip_serializing)]
+    pub legacy_preferences: Option<Preferences>,
+    pub state: State,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            credentials: Credentials::default(),
+            preferences: Preferences::default(),
+            legacy_preferences: None,
+            state: State::default(),
+        }
+    }
+}
+
+// Everything the user chooses, i.e. the contents of settings.toml
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub log_level: LogLevel,
+    pub endpoints: EndpointsConfig,
+    pub ack_policy: AckPolicy,
+    pub credentials: CredentialsConfig,
+    pub poll_interval_secs: u64,
+    pub websocket_failures_before_polling: u32,
+    pub recent_messages: usize,
+    pub pause_bypass_emergency: bool,
+    pub quiet_hours: QuietHoursConfig,
+    pub hooks: Vec<HookConfig>,
+    pub webhooks: Vec<WebhookConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub proxy: ProxyConfig,
+    pub tls: TlsConfig,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Debug,
+            endpoints: EndpointsConfig::default(),
+            ack_policy: AckPolicy::Auto,
+            credentials: CredentialsConfig::default(),
+            poll_interval_secs: 60,
+            websocket_failures_before_polling: 3,
+            recent_messages: 10,
+            pause_bypass_emergency: true,
+            quiet_hours: QuietHoursConfig::default(),
+            hooks: vec![],
+            webhooks: vec![],
+            mqtt: None,
+            proxy: ProxyConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+// What miniover keeps track of between runs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    // Follows the system autostart setting, which is looked up again on every start
     pub start_on_boot: bool,
     pub last_message_id: Option<String>,
+    pub pause: Option<PauseState>,
+    // Messages held back by quiet hours, shown once they end
+    pub quiet_held: Vec<Message>,
 }
 
-impl Default for Config {
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+// Pushover repeats emergency-priority messages until someone acknowledges them
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckPolicy {
+    // As soon as the message arrives, even if a pause or quiet hours hold it back
+    Auto,
+    // Only through `miniover ack`, the control socket or D-Bus
+    Manual,
+}
+
+// Proxy for both REST and WebSocket traffic (http://, socks5:// or socks5h://)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub url: Option<String>,
+    pub from_env: bool,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            from_env: true,
+        }
+    }
+}
+
+// Extra trusted CAs (PEM bundles) and optional SPKI pins for the Pushover hosts
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub extra_ca_certs: Vec<PathBuf>,
+    pub pinned_spki_sha256: Vec<String>,
+}
+
+// How the message feed is currently receiving messages
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    WebSocket,
+    Polling,
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::WebSocket => write!(f, "websocket"),
+            Transport::Polling => write!(f, "http polling"),
+        }
+    }
+}
+
+// Connection state machine published by the message feed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Polling,
+    LoggedOut,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "Connecting"),
+            ConnectionState::Connected => write!(f, "Connected"),
+            ConnectionState::Reconnecting => write!(f, "Reconnecting"),
+            ConnectionState::Polling => write!(f, "Connected (polling)"),
+            ConnectionState::LoggedOut => write!(f, "Logged out"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub transport: Transport,
+    pub last_connected: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+impl Default for ConnectionStatus {
     fn default() -> Self {
         Self {
-            user_key: None,
-            secret: None,
-            device_id: None,
-            start_on_boot: false,
-            last_message_id: None,
+            state: ConnectionState::Connecting,
+            transport: Transport::WebSocket,
+            last_connected: None,
+            last_error: None,
         }
     }
 }
@@ -41,7 +241,7 @@ pub struct DeviceRegisterResponse {
     pub request: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Message {
     pub id: i64,
     pub id_str: String,
@@ -70,11 +270,21 @@ pub struct MessagesResponse {
     pub messages: Vec<Message>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     Quit,
     ToggleStartOnBoot,
     ShowLogs,
     ShowAbout,
     Logout,
+    // The feed added messages to AppState::recent
+    MessagesDelivered,
+    // A recent messages menu entry was clicked (message id)
+    ShowRecentMessage(i64),
+    Pause(PauseDuration),
+    Resume,
+    // Acknowledge an emergency message by its receipt
+    Acknowledge(String),
+    // Drop the current connection and reconnect right away
+    Reconnect,
 }
\ No newline at end of file
@@ -2,55 +2,246 @@ use crate::types::Config;
 use crate::auth::{login, register_device};
 use crate::toast::{show_success_notification, show_error_notification};
 use crate::creds::get_credentials;
-use anyhow::{Result, Error};
+use crate::migrate;
+use crate::secrets;
+use crate::settings::{self, Override};
+use anyhow::{anyhow, Context, Result, Error};
 use std::{fs, io::Write, path::{Path, PathBuf}};
-use log::{info, error, debug};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{info, warn, error, debug};
 
 #[cfg(windows)]
 use auto_launch::AutoLaunch;
 
-const CONFIG_FILENAME: &str = "config.json";
+const APP_DIR_NAME: &str = "miniover";
+const STATE_FILENAME: &str = "state.json";
+// A file with this name next to the executable switches on portable mode
+const PORTABLE_MARKER: &str = "portable";
+const CONFIG_DIR_ENV: &str = "MINIOVER_CONFIG_DIR";
+const DATA_DIR_ENV: &str = "MINIOVER_DATA_DIR";
 
-pub fn get_app_config_dir() -> PathBuf {
-    let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push("miniover");
-    std::fs::create_dir_all(&path).ok();
-    path
+// Before the state dir, runtime state lived in the config dir: (old name, name in the state dir)
+const LEGACY_STATE_FILES: [(&str, &str); 4] = [
+    ("config.json", STATE_FILENAME),
+    ("config.json.bak", "state.json.bak"),
+    (crate::outbox::OUTBOX_FILENAME, crate::outbox::OUTBOX_FILENAME),
+    (crate::webhook::QUEUE_FILENAME, crate::webhook::QUEUE_FILENAME),
+];
+
+static APP_PATHS: OnceLock<AppPaths> = OnceLock::new();
+
+#[derive(Clone, Debug)]
+pub struct AppPaths {
+    // settings.toml, rules.json and credentials.enc
+    pub config_dir: PathBuf,
+    pub data_dir: PathBuf,
+    // What miniover keeps track of between runs: state.json, the outbox and webhook queue
+    pub state_dir: PathBuf,
+    pub log_dir: PathBuf,
+    // The state dir came from --data-dir, MINIOVER_DATA_DIR or portable mode, so this may be
+    // one of several instances
+    pub custom_state_dir: bool,
+}
+
+// Directories given with --config-dir, --data-dir and --portable
+#[derive(Debug, Default)]
+pub struct DirOptions {
+    pub config_dir: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub portable: bool,
+}
+
+// Work out and create the directories, once at startup. The flags win over MINIOVER_CONFIG_DIR
+// and MINIOVER_DATA_DIR, which win over portable mode, which wins over the platform's usual
+// places (XDG on Linux, %APPDATA% on Windows).
+pub fn init_paths(options: &DirOptions) -> Result<&'static AppPaths> {
+    let paths = resolve_paths(options)?;
+    for dir in [&paths.config_dir, &paths.data_dir, &paths.state_dir, &paths.log_dir] {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    Ok(APP_PATHS.get_or_init(|| paths))
+}
+
+pub fn app_paths() -> &'static AppPaths {
+    APP_PATHS.get().expect("init_paths runs before anything uses the directories")
 }
 
-pub fn get_app_paths() -> (std::path::PathBuf, std::path::PathBuf) {
-    let config_dir = get_app_config_dir();
+fn resolve_paths(options: &DirOptions) -> Result<AppPaths> {
+    let portable_dir = match options.portable {
+        true => Some(exe_dir()?),
+        false => exe_dir().ok().filter(|dir| dir.join(PORTABLE_MARKER).exists()),
+    };
     
-    let mut log_dir = dirs::data_dir().unwrap_or_else(|| std::path::PathBuf::from("."));
-    log_dir.push("miniover");
-    log_dir.push("logs");
-    std::fs::create_dir_all(&log_dir).ok();
+    let config_dir = match (dir_option(&options.config_dir, CONFIG_DIR_ENV)?, &portable_dir) {
+        (Some(dir), _) => dir,
+        (None, Some(portable_dir)) => portable_dir.join("config"),
+        (None, None) => dirs::config_dir()
+            .ok_or_else(|| anyhow!("Could not determine the config directory, set {} or pass --config-dir", CONFIG_DIR_ENV))?
+            .join(APP_DIR_NAME),
+    };
     
-    (config_dir, log_dir)
+    let data_dir_option = dir_option(&options.data_dir, DATA_DIR_ENV)?;
+    let custom_state_dir = data_dir_option.is_some() || portable_dir.is_some();
+    let (data_dir, state_dir) = match (data_dir_option, &portable_dir) {
+        (Some(dir), _) => (dir.clone(), dir),
+        (None, Some(portable_dir)) => (portable_dir.join("data"), portable_dir.join("data")),
+        (None, None) => {
+            let data_dir = dirs::data_dir()
+                .ok_or_else(|| anyhow!("Could not determine the data directory, set {} or pass --data-dir", DATA_DIR_ENV))?
+                .join(APP_DIR_NAME);
+            // $XDG_STATE_HOME on Linux; Windows has no such thing and keeps state with the data
+            let state_dir = dirs::state_dir().map(|dir| dir.join(APP_DIR_NAME)).unwrap_or_else(|| data_dir.clone());
+            (data_dir, state_dir)
+        }
+    };
+    
+    Ok(AppPaths {
+        log_dir: data_dir.join("logs"),
+        config_dir,
+        data_dir,
+        state_dir,
+        custom_state_dir,
+    })
 }
 
-pub fn save_config(config: &Config, config_dir: &Path) -> Result<()> {
-    let config_path = config_dir.join(CONFIG_FILENAME);
-    let config_json = serde_json::to_string_pretty(config)?;
+// The flag if given, otherwise the environment variable, made absolute
+fn dir_option(flag: &Option<PathBuf>, env: &str) -> Result<Option<PathBuf>> {
+    let dir = flag.clone().or_else(|| std::env::var_os(env).filter(|dir| !dir.is_empty()).map(PathBuf::from));
+    dir.map(|dir| std::path::absolute(&dir).with_context(|| format!("Invalid directory {}", dir.display())))
+        .transpose()
+}
+
+fn exe_dir() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Could not locate the executable for portable mode")?;
+    exe.parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("Could not locate the executable for portable mode"))
+}
+
+// Move state an older version left in the config dir over to the state dir
+pub fn move_legacy_state(paths: &AppPaths) {
+    for (old_name, new_name) in LEGACY_STATE_FILES {
+        let (from, to) = (paths.config_dir.join(old_name), paths.state_dir.join(new_name));
+        if from == to || !from.exists() || to.exists() {
+            continue;
+        }
+        // A rename can't cross file systems, copy instead
+        let moved = fs::rename(&from, &to).or_else(|_| fs::copy(&from, &to).and_then(|_| fs::remove_file(&from)));
+        match moved {
+            Ok(()) => info!("Moved {} to {}", from.display(), to.display()),
+            Err(e) => warn!("Failed to move {} to {}: {}", from.display(), to.display(), e),
+        }
+    }
+    // Only meaningful while the old version was running
+    fs::remove_file(paths.config_dir.join(crate::status::STATUS_FILENAME)).ok();
+}
+
+// Where `name` would be run from, for tests that need an external program
+#[cfg(test)]
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Replace `path` without ever leaving a half-written file behind: write a temp file next to
+// it, fsync, then rename over the original. Only the user can read it on Unix.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp_name = path.file_name().ok_or_else(|| Error::msg("Not a file path"))?.to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    
+    let written = options.open(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        fs::remove_file(&temp_path).ok();
+        return Err(Error::new(e).context(format!("Failed to write {}", temp_path.display())));
+    }
+    
+    #[cfg(unix)]
+    {
+        // The mode only applies to new files, tighten a temp file left over from an older version
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600))?;
+    }
     
-    let mut file = fs::File::create(config_path)?;
-    file.write_all(config_json.as_bytes())?;
+    fs::rename(&temp_path, path)?;
+    
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent).and_then(|dir| dir.sync_all()).ok();
+    }
     
     Ok(())
 }
 
-pub fn load_config(config_dir: &Path) -> Result<Config> {
-    let config_path = config_dir.join(CONFIG_FILENAME);
+fn backup_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(format!("{}.bak", STATE_FILENAME))
+}
+
+pub fn save_config(config: &Config, state_dir: &Path) -> Result<()> {
+    let config_path = state_dir.join(STATE_FILENAME);
+    let config_json = serde_json::to_string_pretty(config)?;
+    
+    // Keep the previous version around for load_config, but never replace a good backup
+    // with a file that doesn't parse. It goes through serde again so credentials from an
+    // older plaintext config don't end up in the backup.
+    if let Ok(previous) = read_config(&config_path) {
+        let backup = serde_json::to_string_pretty(&previous)?;
+        if let Err(e) = write_atomic(&backup_path(&config_path), backup.as_bytes()) {
+            warn!("Failed to back up config: {}", e);
+        }
+    }
+    
+    write_atomic(&config_path, config_json.as_bytes())
+}
+
+fn read_config(config_path: &Path) -> Result<Config> {
+    let config_data = fs::read_to_string(config_path)?;
+    let config = migrate::upgrade(serde_json::from_str(&config_data)?)?;
+    Ok(serde_json::from_value(config)?)
+}
+
+pub fn load_config(state_dir: &Path) -> Result<Config> {
+    let config_path = state_dir.join(STATE_FILENAME);
     
     if !config_path.exists() {
         info!("No config file found, using default");
         return Ok(Config::default());
     }
     
-    let config_data = fs::read_to_string(config_path)?;
-    let config: Config = serde_json::from_str(&config_data)?;
+    let error = match read_config(&config_path) {
+        Ok(config) => return Ok(config),
+        Err(e) => e,
+    };
     
-    Ok(config)
+    // Fall back to the copy save_config keeps; the broken file is replaced on the next save
+    let backup = backup_path(&config_path);
+    match read_config(&backup) {
+        Ok(config) => {
+            error!("Failed to load {}: {}", config_path.display(), error);
+            warn!("Recovered config from {}", backup.display());
+            Ok(config)
+        }
+        Err(_) => Err(error.context(format!("Failed to load {}", config_path.display()))),
+    }
 }
 
 // ============================================================================
@@ -65,12 +256,11 @@ pub fn is_autostart_enabled() -> Result<bool> {
 
 #[cfg(windows)]
 pub async fn toggle_autorun() -> Result<()> {
-    let config_dir = crate::utils::get_app_config_dir();
-    let config = load_config(&config_dir)?;
+    let config = load_config(&app_paths().state_dir)?;
     
     let auto_launch = AutoLaunch::new("Miniover", std::env::current_exe()?.to_str().unwrap(), &[""]);
     
-    match (config.start_on_boot, auto_launch.is_enabled()?) {
+    match (config.state.start_on_boot, auto_launch.is_enabled()?) {
         (true, false) => auto_launch.enable()?,
         (false, true) => auto_launch.disable()?,
         _ => {}
@@ -80,15 +270,42 @@ pub async fn toggle_autorun() -> Result<()> {
 }
 
 // ============================================================================
-// Linux autostart using systemd user service
+// Linux autostart: a generated systemd user service, or an XDG autostart entry
+// on systems without a systemd user manager
 // ============================================================================
 
+#[cfg(target_os = "linux")]
+const SERVICE_NAME: &str = "miniover.service";
+#[cfg(target_os = "linux")]
+const DESKTOP_ENTRY_NAME: &str = "miniover.desktop";
+// First line of units we wrote ourselves; anything else was installed by the user or a package
+#[cfg(target_os = "linux")]
+const GENERATED_UNIT_MARKER: &str = "# Generated by miniover";
+// The shipped unit is the template, only ExecStart changes
+#[cfg(target_os = "linux")]
+const SERVICE_TEMPLATE: &str = include_str!("miniover.service");
+
 #[cfg(target_os = "linux")]
 pub fn is_autostart_enabled() -> Result<bool> {
-    use std::process::Command;
+    if has_systemd_user_manager() && is_service_enabled() {
+        return Ok(true);
+    }
     
-    let output = Command::new("systemctl")
-        .args(["--user", "is-enabled", "miniover.service"])
+    let desktop_entry = desktop_entry_path()?;
+    match fs::read_to_string(&desktop_entry) {
+        Ok(contents) => {
+            let is_enabled = !contents.lines().any(|line| line.trim() == "Hidden=true");
+            debug!("Autostart entry {} found (enabled={})", desktop_entry.display(), is_enabled);
+            Ok(is_enabled)
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_service_enabled() -> bool {
+    let output = std::process::Command::new("systemctl")
+        .args(["--user", "is-enabled", SERVICE_NAME])
         .output();
     
     match output {
@@ -96,100 +313,227 @@ pub fn is_autostart_enabled() -> Result<bool> {
             let status = String::from_utf8_lossy(&output.stdout);
             let is_enabled = status.trim() == "enabled";
             debug!("systemd service status: {} (enabled={})", status.trim(), is_enabled);
-            Ok(is_enabled)
+            is_enabled
         }
         Err(e) => {
             debug!("Failed to check systemd service status: {}", e);
-            Ok(false)
+            false
         }
     }
 }
 
+// True when a systemd user instance is running and reachable for this session
+#[cfg(target_os = "linux")]
+fn has_systemd_user_manager() -> bool {
+    if !Path::new("/run/systemd/system").exists() {
+        return false;
+    }
+    
+    std::process::Command::new("systemctl")
+        .args(["--user", "show-environment"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(target_os = "linux")]
+fn systemctl(args: &[&str]) -> Result<()> {
+    let output = std::process::Command::new("systemctl").arg("--user").args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::msg(format!("systemctl --user {} failed: {}", args.join(" "), stderr.trim())));
+    }
+    Ok(())
+}
+
 #[cfg(target_os = "linux")]
-fn is_service_installed() -> bool {
-    // Check if the service file exists in the user's systemd directory
-    if let Some(config_dir) = dirs::config_dir() {
-        let service_path = config_dir.join("systemd/user/miniover.service");
-        if service_path.exists() {
-            return true;
+fn user_unit_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().ok_or_else(|| Error::msg("Could not determine the config directory"))?;
+    Ok(config_dir.join("systemd/user").join(SERVICE_NAME))
+}
+
+#[cfg(target_os = "linux")]
+fn desktop_entry_path() -> Result<PathBuf> {
+    let config_dir = dirs::config_dir().ok_or_else(|| Error::msg("Could not determine the config directory"))?;
+    Ok(config_dir.join("autostart").join(DESKTOP_ENTRY_NAME))
+}
+
+// The shipped unit with ExecStart pointing at this binary
+#[cfg(target_os = "linux")]
+fn generate_unit(exe: &Path) -> String {
+    // systemd unquotes and unescapes ExecStart, and expands % specifiers and $VARIABLES even in quotes
+    let exe = exe
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    
+    let mut unit = format!("{}, \"Start on boot\" rewrites this file\n", GENERATED_UNIT_MARKER);
+    for line in SERVICE_TEMPLATE.lines() {
+        if line.starts_with("ExecStart=") {
+            unit.push_str(&format!("ExecStart=\"{}\"\n", exe));
+        } else {
+            unit.push_str(line);
+            unit.push('\n');
         }
     }
+    unit
+}
+
+#[cfg(target_os = "linux")]
+fn generate_desktop_entry(exe: &Path) -> String {
+    // Quoted per the desktop entry spec, then escaped again as a string value
+    let exe = exe
+        .to_string_lossy()
+        .replace('\\', "\\\\\\\\")
+        .replace('"', "\\\\\"")
+        .replace('`', "\\\\`")
+        .replace('$', "\\\\$")
+        .replace('%', "%%");
     
-    // Also check system-wide user services
-    let system_service = PathBuf::from("/usr/lib/systemd/user/miniover.service");
-    if system_service.exists() {
-        return true;
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=Miniover\n\
+         Comment=Minimal Pushover client\n\
+         Exec=\"{}\"\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        exe
+    )
+}
+
+// Write our unit unless the user or a package already provides one, then enable it
+#[cfg(target_os = "linux")]
+fn enable_service(exe: &Path) -> Result<()> {
+    let unit_path = user_unit_path()?;
+    let packaged = Path::new("/usr/lib/systemd/user").join(SERVICE_NAME);
+    let existing = fs::read_to_string(&unit_path).ok();
+    
+    let ours = existing.as_deref().is_none_or(|unit| unit.starts_with(GENERATED_UNIT_MARKER));
+    if ours && (existing.is_some() || !packaged.exists()) {
+        let unit = generate_unit(exe);
+        if existing.as_deref() != Some(unit.as_str()) {
+            info!("Installing systemd user service at {}", unit_path.display());
+            if let Some(parent) = unit_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&unit_path, unit)?;
+            systemctl(&["daemon-reload"])?;
+        }
+    } else {
+        info!("Using the existing systemd user service");
     }
     
-    false
+    systemctl(&["enable", SERVICE_NAME])
+}
+
+#[cfg(target_os = "linux")]
+fn enable_desktop_entry(exe: &Path) -> Result<()> {
+    let entry_path = desktop_entry_path()?;
+    info!("No systemd user manager, installing autostart entry at {}", entry_path.display());
+    if let Some(parent) = entry_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&entry_path, generate_desktop_entry(exe))?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn remove_desktop_entry() -> Result<()> {
+    let entry_path = desktop_entry_path()?;
+    match fs::remove_file(&entry_path) {
+        Ok(()) => {
+            info!("Removed autostart entry {}", entry_path.display());
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
 }
 
 #[cfg(target_os = "linux")]
 pub async fn toggle_autorun() -> Result<()> {
-    use std::process::Command;
-    
-    let config_dir = crate::utils::get_app_config_dir();
-    let config = load_config(&config_dir)?;
+    let config = load_config(&app_paths().state_dir)?;
     let is_enabled = is_autostart_enabled().unwrap_or(false);
+    let systemd = has_systemd_user_manager();
     
-    match (config.start_on_boot, is_enabled) {
+    let result = match (config.state.start_on_boot, is_enabled) {
         (true, false) => {
-            // Check if service is installed first
-            if !is_service_installed() {
-                error!("Systemd service not installed");
-                show_error_notification(
-                    "Service Not Installed",
-                    "Please install miniover.service first.\nSee README for instructions."
-                )?;
-                return Err(Error::msg("Systemd service not installed. Copy miniover.service to ~/.config/systemd/user/"));
-            }
-            
-            // Enable the service
-            info!("Enabling systemd user service");
-            let output = Command::new("systemctl")
-                .args(["--user", "enable", "miniover.service"])
-                .output()?;
-            
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                error!("Failed to enable service: {}", stderr);
-                show_error_notification("Failed to Enable", &format!("Could not enable service: {}", stderr))?;
-                return Err(Error::msg(format!("Failed to enable service: {}", stderr)));
+            let exe = std::env::current_exe()?;
+            if systemd {
+                info!("Enabling systemd user service");
+                // Don't start twice if an autostart entry is left over from before
+                enable_service(&exe).and_then(|()| remove_desktop_entry())
+            } else {
+                enable_desktop_entry(&exe)
             }
         }
         (false, true) => {
-            // Disable the service
-            info!("Disabling systemd user service");
-            let output = Command::new("systemctl")
-                .args(["--user", "disable", "miniover.service"])
-                .output()?;
-            
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                error!("Failed to disable service: {}", stderr);
-                show_error_notification("Failed to Disable", &format!("Could not disable service: {}", stderr))?;
-                return Err(Error::msg(format!("Failed to disable service: {}", stderr)));
-            }
+            info!("Disabling autostart");
+            let service = match systemd {
+                true => systemctl(&["disable", SERVICE_NAME]),
+                false => Ok(()),
+            };
+            service.and(remove_desktop_entry())
         }
-        _ => {}
-    }
+        _ => Ok(()),
+    };
     
-    Ok(())
+    if let Err(e) = &result {
+        error!("Failed to update autostart: {}", e);
+        show_error_notification("Autostart Failed", &format!("Could not update autostart: {}", e))?;
+    }
+    result
 }
 
 // ============================================================================
 // Config initialization
 // ============================================================================
 
-pub async fn init_config() -> Result<Config, Error> {
-    let (config_dir, _) = get_app_paths();
-    let mut config = load_config(&config_dir)?;
+pub async fn init_config(overrides: &[Override]) -> Result<Config, Error> {
+    let AppPaths { config_dir, state_dir, .. } = app_paths();
+    let mut config = load_config(state_dir)?;
+    
+    // Preferences live in settings.toml, older versions kept them in config.json
+    settings::create_if_missing(config_dir, config.legacy_preferences.take().as_ref())?;
+    config.preferences = match settings::load(config_dir, None, overrides) {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            error!("Invalid settings: {:#}", e);
+            show_error_notification("Invalid Settings", &format!("{:#}", e)).ok();
+            return Err(e);
+        }
+    };
+    log::set_max_level(config.preferences.log_level.into());
+    
+    // Endpoint and proxy settings must be in place before the login requests
+    crate::net::configure(&config)?;
+    
+    // Credentials live in the keyring (or an encrypted file), not in state.json
+    let store = match secrets::open(&config, config_dir, true) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Credential storage unavailable: {:#}", e);
+            show_error_notification("Credential Storage Unavailable", &format!("{:#}", e)).ok();
+            return Err(e);
+        }
+    };
+    secrets::load_credentials(store.as_ref(), &mut config, state_dir)?;
 
     // Load autorun status from system
-    config.start_on_boot = is_autostart_enabled().unwrap_or(false);
+    config.state.start_on_boot = is_autostart_enabled().unwrap_or(false);
+    
+    // Write it back in the current schema, which also replaces a file that had to be
+    // recovered from the backup
+    if let Err(e) = save_config(&config, state_dir) {
+        error!("Failed to save config: {}", e);
+    }
     
     // Check if login is needed
-    if config.user_key.is_none() || config.secret.is_none() || config.device_id.is_none() {
+    if config.credentials.user_key.is_none() || config.credentials.secret.is_none() || config.credentials.device_id.is_none() {
         info!("Login required, showing login dialog");
         
         // Use credential dialog to get email and password
@@ -203,11 +547,15 @@ pub async fn init_config() -> Result<Config, Error> {
                             info!("Device registered: {}", device_response.id);
                             
                             // Update config
-                            config.user_key = Some(login_response.id);
-                            config.secret = Some(login_response.secret);
-                            config.device_id = Some(device_response.id);
+                            config.credentials.user_key = Some(login_response.id);
+                            config.credentials.secret = Some(login_response.secret);
+                            config.credentials.device_id = Some(device_response.id);
                             
-                            if let Err(e) = save_config(&config, &config_dir) {
+                            if let Err(e) = store.save(&config.credentials) {
+                                error!("Failed to store credentials: {:#}", e);
+                                show_error_notification("Failed to Store Credentials", &format!("You will have to log in again next time: {:#}", e)).ok();
+                            }
+                            if let Err(e) = save_config(&config, state_dir) {
                                 error!("Failed to save config: {}", e);
                             }
                             
@@ -235,3 +583,107 @@ pub async fn init_config() -> Result<Config, Error> {
     
     Ok(config)
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    
+    const PATHS: [&str; 8] = [
+        "/usr/bin/miniover",
+        "/home/jo doe/.local/bin/miniover",
+        "/opt/\"quoted\"/miniover",
+        "/opt/it's/miniover",
+        "/opt/100%/miniover",
+        "/opt/$HOME/${USER}/miniover",
+        "/opt/back\\slash/`tick`/miniover",
+        "/opt/a \\\"b\\\" $c %d%%/mini over",
+    ];
+    
+    // What systemd makes of ExecStart: % specifiers and $ variables resolved, then the quoted word unescaped
+    fn systemd_exec_start(unit: &str) -> String {
+        let value = unit.lines().find_map(|line| line.strip_prefix("ExecStart=")).unwrap();
+        let quoted = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'));
+        let mut chars = quoted.unwrap_or_else(|| panic!("unquoted: {}", value)).chars();
+        
+        let mut path = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => {
+                    assert_eq!(chars.next(), Some('%'), "specifier in {}", value);
+                    path.push('%');
+                }
+                '$' => {
+                    assert_eq!(chars.next(), Some('$'), "variable in {}", value);
+                    path.push('$');
+                }
+                '\\' => path.push(chars.next().unwrap()),
+                '"' => panic!("ends early: {}", value),
+                c => path.push(c),
+            }
+        }
+        path
+    }
+    
+    // What a launcher makes of Exec: the string value unescaped, %% resolved, then the quoted argument unescaped
+    fn desktop_exec(entry: &str) -> String {
+        let value = entry.lines().find_map(|line| line.strip_prefix("Exec=")).unwrap();
+        let mut string = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('\\') => string.push('\\'),
+                    Some('s') => string.push(' '),
+                    other => panic!("unknown string escape {:?} in {}", other, value),
+                },
+                '%' => {
+                    assert_eq!(chars.next(), Some('%'), "field code in {}", value);
+                    string.push('%');
+                }
+                c => string.push(c),
+            }
+        }
+        
+        let quoted = string.strip_prefix('"').and_then(|string| string.strip_suffix('"'));
+        let mut chars = quoted.unwrap_or_else(|| panic!("unquoted: {}", string)).chars();
+        let mut path = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(c @ ('"' | '`' | '$' | '\\')) => path.push(c),
+                    other => panic!("unknown escape {:?} in {}", other, string),
+                },
+                '"' | '`' | '$' => panic!("unescaped {} in {}", c, string),
+                c => path.push(c),
+            }
+        }
+        path
+    }
+    
+    #[test]
+    fn unit_runs_the_given_path() {
+        for path in PATHS {
+            let unit = generate_unit(Path::new(path));
+            assert_eq!(systemd_exec_start(&unit), path);
+            assert!(unit.starts_with(GENERATED_UNIT_MARKER));
+            assert_eq!(unit.lines().count(), SERVICE_TEMPLATE.lines().count() + 1, "{}", path);
+        }
+        
+        let unit = generate_unit(Path::new(PATHS[7]));
+        let exec_start = unit.lines().find(|line| line.starts_with("ExecStart="));
+        assert_eq!(exec_start, Some(r##"ExecStart="/opt/a \\\"b\\\" $$c %%d%%%%/mini over""##));
+    }
+    
+    #[test]
+    fn desktop_entry_runs_the_given_path() {
+        for path in PATHS {
+            let entry = generate_desktop_entry(Path::new(path));
+            assert_eq!(desktop_exec(&entry), path);
+            assert!(entry.starts_with("[Desktop Entry]\n"));
+        }
+        
+        let entry = generate_desktop_entry(Path::new(PATHS[7]));
+        let exec = entry.lines().find(|line| line.starts_with("Exec="));
+        assert_eq!(exec, Some(r##"Exec="/opt/a \\\\\\"b\\\\\\" \\$c %%d%%%%/mini over""##));
+    }
+}
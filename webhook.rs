@@ -0,0 +1,670 @@
+use crate::net::http_client;
+use crate::rules::{MatchSpec, Matcher};
+use crate::types::Message;
+use crate::utils::{unix_now, write_atomic};
+use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time;
+
+pub const QUEUE_FILENAME: &str = "webhook_queue.json";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+const RETRY_BASE_DELAY_SECS: i64 = 5;
+const RETRY_MAX_DELAY_SECS: i64 = 30 * 60;
+
+// An HTTP endpoint that gets a JSON copy of selected messages
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    // Signs the body with HMAC-SHA256, sent as "sha256=<hex>"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hmac_secret: Option<String>,
+    #[serde(default = "default_signature_header")]
+    pub signature_header: String,
+    // Give up on a message after this many failed deliveries
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    // Forward every message this matches; without it only rules' forward actions use the sink
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matcher: Option<MatchSpec>,
+}
+
+fn default_signature_header() -> String {
+    "X-Miniover-Signature".to_string()
+}
+
+fn default_max_attempts() -> u32 {
+    20
+}
+
+// One pending POST; the body is rendered when queued so retries send the same bytes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Delivery {
+    sink: String,
+    message_id: i64,
+    body: String,
+    queued_at: i64,
+    attempts: u32,
+    next_attempt_at: i64,
+    last_error: Option<String>,
+}
+
+// Deliveries not yet accepted by their endpoint, kept on disk across restarts
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Queue {
+    entries: Vec<Delivery>,
+}
+
+// Handle used by the message feed to forward messages
+#[derive(Default)]
+pub struct Webhooks {
+    sinks: Vec<(String, Option<Matcher>)>,
+    // Unbounded so a dead endpoint can never hold up the message feed
+    tx: Option<mpsc::UnboundedSender<Delivery>>,
+    // Lets the delivery task pick up edited sinks
+    configs: Option<watch::Sender<Vec<WebhookConfig>>>,
+}
+
+impl Webhooks {
+    // Validate the sinks and start delivering, including anything queued by a previous run
+    pub fn start(configs: &[WebhookConfig], state_dir: &Path) -> Result<Self> {
+        let sinks = compile(configs)?;
+        
+        let queue = Queue::load(state_dir).unwrap_or_else(|e| {
+            error!("Failed to load webhook queue, starting empty: {}", e);
+            Queue::default()
+        });
+        
+        if configs.is_empty() && queue.entries.is_empty() {
+            return Ok(Self::default());
+        }
+        
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (configs_tx, configs_rx) = watch::channel(configs.to_vec());
+        tokio::spawn(deliver(configs_rx, state_dir.to_path_buf(), queue, rx));
+        
+        Ok(Self {
+            sinks,
+            tx: Some(tx),
+            configs: Some(configs_tx),
+        })
+    }
+    
+    pub fn check(configs: &[WebhookConfig]) -> Result<()> {
+        compile(configs).map(|_| ())
+    }
+    
+    // Switch to edited sinks, keeping queued deliveries; nothing changes if they are invalid
+    pub fn reconfigure(&mut self, configs: &[WebhookConfig], state_dir: &Path) -> Result<()> {
+        let Some(configs_tx) = &self.configs else {
+            *self = Self::start(configs, state_dir)?;
+            return Ok(());
+        };
+        
+        self.sinks = compile(configs)?;
+        configs_tx.send_replace(configs.to_vec());
+        Ok(())
+    }
+    
+    pub fn names(&self) -> Vec<&str> {
+        self.sinks.iter().map(|(name, _)| name.as_str()).collect()
+    }
+    
+    // Queue the message for every sink that matches it or was requested by a rule
+    pub fn dispatch(&self, message: &Message, requested: &[String]) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        
+        let targets: Vec<&String> = self
+            .sinks
+            .iter()
+            .filter(|(name, matcher)| {
+                matcher.as_ref().is_some_and(|matcher| matcher.matches(message)) || requested.contains(name)
+            })
+            .map(|(name, _)| name)
+            .collect();
+        
+        if targets.is_empty() {
+            return;
+        }
+        
+        let body = match serde_json::to_string(message) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to render message {} for webhooks: {}", message.id, e);
+                return;
+            }
+        };
+        
+        for name in targets {
+            let now = unix_now();
+            let delivery = Delivery {
+                sink: name.clone(),
+                message_id: message.id,
+                body: body.clone(),
+                queued_at: now,
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+            };
+            if tx.send(delivery).is_err() {
+                error!("Webhook delivery task is gone, not forwarding message {}", message.id);
+            }
+        }
+    }
+}
+
+fn compile(configs: &[WebhookConfig]) -> Result<Vec<(String, Option<Matcher>)>> {
+    let mut names = HashSet::new();
+    let mut sinks = Vec::new();
+    
+    for config in configs {
+        if config.name.is_empty() {
+            return Err(anyhow!("Webhook without a name"));
+        }
+        if !names.insert(config.name.as_str()) {
+            return Err(anyhow!("Duplicate webhook '{}'", config.name));
+        }
+        validate(config).with_context(|| format!("In webhook '{}'", config.name))?;
+        
+        let matcher = config.matcher.as_ref().map(Matcher::compile).transpose()?;
+        sinks.push((config.name.clone(), matcher));
+    }
+    Ok(sinks)
+}
+
+fn validate(config: &WebhookConfig) -> Result<()> {
+    let url = Url::parse(&config.url).context("Invalid URL")?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("URL must be http or https"));
+    }
+    
+    for (name, value) in &config.headers {
+        HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("Invalid header name '{}'", name))?;
+        HeaderValue::from_str(value).with_context(|| format!("Invalid value for header '{}'", name))?;
+    }
+    HeaderName::from_bytes(config.signature_header.as_bytes()).context("Invalid signature_header")?;
+    
+    if config.max_attempts == 0 {
+        return Err(anyhow!("max_attempts must be at least 1"));
+    }
+    Ok(())
+}
+
+impl Queue {
+    fn load(state_dir: &Path) -> Result<Queue> {
+        let queue_path = state_dir.join(QUEUE_FILENAME);
+        
+        if !queue_path.exists() {
+            return Ok(Queue::default());
+        }
+        
+        let queue_data = fs::read_to_string(queue_path)?;
+        Ok(serde_json::from_str(&queue_data)?)
+    }
+    
+    fn save(&self, state_dir: &Path) -> Result<()> {
+        let queue_path = state_dir.join(QUEUE_FILENAME);
+        let queue_json = serde_json::to_string_pretty(self)?;
+        
+        // A crash mid-write must not lose the queue
+        write_atomic(&queue_path, queue_json.as_bytes())
+    }
+    
+    // Time until the next delivery to a sink that isn't busy is due
+    fn next_due(&self, busy: &HashSet<String>) -> Option<Duration> {
+        let now = unix_now();
+        self.entries
+            .iter()
+            .filter(|entry| !busy.contains(&entry.sink))
+            .map(|entry| entry.next_attempt_at)
+            .min()
+            .map(|at| Duration::from_secs((at - now).max(0) as u64))
+    }
+    
+    fn position(&self, attempt: &Attempt) -> Option<usize> {
+        self.entries.iter().position(|entry| {
+            entry.sink == attempt.sink && entry.message_id == attempt.message_id && entry.queued_at == attempt.queued_at
+        })
+    }
+}
+
+// A finished POST of a queued delivery
+struct Attempt {
+    sink: String,
+    message_id: i64,
+    queued_at: i64,
+    max_attempts: u32,
+    result: Result<(), Failure>,
+}
+
+// Owns the queue: takes new deliveries from the feed and posts due ones. Each sink gets one
+// request at a time, in order, while the sinks are posted to concurrently so a slow or dead
+// endpoint only delays its own deliveries.
+async fn deliver(
+    sinks: watch::Receiver<Vec<WebhookConfig>>,
+    state_dir: PathBuf,
+    mut queue: Queue,
+    mut rx: mpsc::UnboundedReceiver<Delivery>,
+) {
+    if !queue.entries.is_empty() {
+        info!("Retrying {} queued webhook deliveries", queue.entries.len());
+    }
+    
+    let mut posts = JoinSet::new();
+    let mut busy = HashSet::new();
+    
+    loop {
+        if start_due(&sinks.borrow(), &mut queue, &mut busy, &mut posts) {
+            save(&queue, &state_dir);
+        }
+        
+        let due = queue.next_due(&busy);
+        tokio::select! {
+            delivery = rx.recv() => match delivery {
+                Some(delivery) => {
+                    // On disk before the first attempt, so a crash mid-request can't lose it
+                    queue.entries.push(delivery);
+                    save(&queue, &state_dir);
+                }
+                // Requests still in flight are abandoned, their deliveries stay queued
+                None => return,
+            },
+            Some(attempt) = posts.join_next() => {
+                let Ok(attempt) = attempt else {
+                    continue;
+                };
+                busy.remove(&attempt.sink);
+                finish(&mut queue, attempt);
+                save(&queue, &state_dir);
+            }
+            _ = time::sleep(due.unwrap_or_default()), if due.is_some() => {}
+        }
+    }
+}
+
+fn save(queue: &Queue, state_dir: &Path) {
+    if let Err(e) = queue.save(state_dir) {
+        error!("Failed to save webhook queue: {}", e);
+    }
+}
+
+// Post the oldest due delivery of every idle sink, returns whether the queue changed
+fn start_due(sinks: &[WebhookConfig], queue: &mut Queue, busy: &mut HashSet<String>, posts: &mut JoinSet<Attempt>) -> bool {
+    let now = unix_now();
+    let before = queue.entries.len();
+    
+    queue.entries.retain(|entry| {
+        let removed = !sinks.iter().any(|sink| sink.name == entry.sink);
+        if removed {
+            warn!("Dropping queued delivery of message {} to removed webhook '{}'", entry.message_id, entry.sink);
+        }
+        !removed
+    });
+    
+    for entry in &queue.entries {
+        if entry.next_attempt_at > now || busy.contains(&entry.sink) {
+            continue;
+        }
+        let Some(sink) = sinks.iter().find(|sink| sink.name == entry.sink) else {
+            continue;
+        };
+        
+        debug!("Posting message {} to webhook '{}'", entry.message_id, sink.name);
+        busy.insert(entry.sink.clone());
+        let (sink, entry) = (sink.clone(), entry.clone());
+        posts.spawn(async move {
+            let result = post(&sink, &entry.body).await;
+            Attempt {
+                sink: sink.name,
+                message_id: entry.message_id,
+                queued_at: entry.queued_at,
+                max_attempts: sink.max_attempts,
+                result,
+            }
+        });
+    }
+    
+    queue.entries.len() != before
+}
+
+fn finish(queue: &mut Queue, attempt: Attempt) {
+    let Some(index) = queue.position(&attempt) else {
+        return;
+    };
+    let (sink, message_id) = (&attempt.sink, attempt.message_id);
+    
+    match attempt.result {
+        Ok(()) => {
+            info!("Message {} forwarded to webhook '{}'", message_id, sink);
+            queue.entries.remove(index);
+        }
+        Err(Failure::Permanent(e)) => {
+            error!("Webhook '{}' rejected message {}, dropping it: {}", sink, message_id, e);
+            queue.entries.remove(index);
+        }
+        Err(Failure::Retry(e)) => {
+            let entry = &mut queue.entries[index];
+            entry.attempts += 1;
+            if entry.attempts >= attempt.max_attempts {
+                error!(
+                    "Giving up on message {} for webhook '{}' after {} attempts: {}",
+                    message_id, sink, entry.attempts, e
+                );
+                queue.entries.remove(index);
+                return;
+            }
+            
+            let now = unix_now();
+            entry.next_attempt_at = now + retry_delay_secs(entry.attempts);
+            entry.last_error = Some(e.to_string());
+            warn!(
+                "Webhook '{}' failed for message {} ({} attempts), retrying in {}s: {}",
+                sink,
+                message_id,
+                entry.attempts,
+                entry.next_attempt_at - now,
+                e
+            );
+        }
+    }
+}
+
+enum Failure {
+    // The endpoint refused the request, retrying won't help
+    Permanent(anyhow::Error),
+    Retry(anyhow::Error),
+}
+
+async fn post(sink: &WebhookConfig, body: &str) -> Result<(), Failure> {
+    let mut request = http_client()
+        .post(&sink.url)
+        .timeout(REQUEST_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json");
+    
+    for (name, value) in &sink.headers {
+        request = request.header(name, value);
+    }
+    
+    if let Some(secret) = &sink.hmac_secret {
+        request = request.header(&sink.signature_header, sign(secret, body));
+    }
+    
+    let response = request
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| Failure::Retry(e.into()))?;
+    
+    let status = response.status();
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => Err(Failure::Retry(anyhow!("HTTP {}", status))),
+        status if status.is_client_error() => Err(Failure::Permanent(anyhow!("HTTP {}", status))),
+        status => Err(Failure::Retry(anyhow!("HTTP {}", status))),
+    }
+}
+
+// "sha256=<hex HMAC of the body>", like GitHub's X-Hub-Signature-256
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+// Exponential backoff: 5s, 10s, 20s, ... capped at 30 minutes
+fn retry_delay_secs(attempts: u32) -> i64 {
+    let exponent = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE_DELAY_SECS << exponent).min(RETRY_MAX_DELAY_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    
+    const WAIT: Duration = Duration::from_secs(10);
+    
+    struct Request {
+        // Names in lowercase
+        headers: BTreeMap<String, String>,
+        body: String,
+    }
+    
+    // A local endpoint answering with `statuses` in turn, the last one repeating
+    async fn endpoint(statuses: &'static [u16]) -> (String, mpsc::UnboundedReceiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut statuses = statuses.iter().copied();
+        let mut status = 200;
+        
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                status = statuses.next().unwrap_or(status);
+                let request = read_request(&mut stream).await;
+                let response = format!("HTTP/1.1 {} Test\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.ok();
+                tx.send(request).ok();
+            }
+        });
+        (url, rx)
+    }
+    
+    // Accepts connections and never answers
+    async fn dead_endpoint() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                open.push(stream);
+            }
+        });
+        url
+    }
+    
+    async fn read_request(stream: &mut TcpStream) -> Request {
+        let mut data = Vec::new();
+        let mut buf = [0; 4096];
+        let header_end = loop {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "connection closed mid-request");
+            data.extend_from_slice(&buf[..read]);
+            if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+        
+        let head = String::from_utf8(data[..header_end].to_vec()).unwrap();
+        let headers: BTreeMap<String, String> = head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+        
+        let length: usize = headers.get("content-length").map_or(0, |length| length.parse().unwrap());
+        while data.len() < header_end + length {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "connection closed mid-body");
+            data.extend_from_slice(&buf[..read]);
+        }
+        let body = String::from_utf8(data[header_end..header_end + length].to_vec()).unwrap();
+        Request { headers, body }
+    }
+    
+    async fn next_request(requests: &mut mpsc::UnboundedReceiver<Request>) -> Request {
+        time::timeout(WAIT, requests.recv()).await.expect("no request in time").unwrap()
+    }
+    
+    // Wait for the queue on disk to get into the expected state
+    async fn queue_on_disk(state_dir: &Path, expected: impl Fn(&Queue) -> bool) -> Queue {
+        let deadline = time::Instant::now() + WAIT;
+        loop {
+            if let Ok(queue) = Queue::load(state_dir)
+                && expected(&queue)
+            {
+                return queue;
+            }
+            assert!(time::Instant::now() < deadline, "webhook queue never got into the expected state");
+            time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+    
+    fn sink(name: &str, url: &str) -> WebhookConfig {
+        WebhookConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            headers: BTreeMap::new(),
+            hmac_secret: None,
+            signature_header: default_signature_header(),
+            max_attempts: default_max_attempts(),
+            matcher: None,
+        }
+    }
+    
+    fn message(id: i64) -> Message {
+        Message {
+            id,
+            message: "Disk full".to_string(),
+            app: "backup".to_string(),
+            priority: 1,
+            ..Default::default()
+        }
+    }
+    
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+    
+    #[test]
+    fn signs_like_github() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+    
+    #[test]
+    fn backs_off_exponentially_up_to_the_cap() {
+        let delays: Vec<i64> = (1..=4).map(retry_delay_secs).collect();
+        assert_eq!(delays, [5, 10, 20, 40]);
+        assert_eq!(retry_delay_secs(12), RETRY_MAX_DELAY_SECS);
+        assert_eq!(retry_delay_secs(u32::MAX), RETRY_MAX_DELAY_SECS);
+    }
+    
+    #[tokio::test]
+    async fn posts_the_message_signed() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let (url, mut requests) = endpoint(&[200]).await;
+        let mut config = sink("ops", &url);
+        config.hmac_secret = Some("s3cret".to_string());
+        config.headers.insert("Authorization".to_string(), "Bearer token".to_string());
+        let webhooks = Webhooks::start(&[config], state_dir.path()).unwrap();
+        
+        webhooks.dispatch(&message(7), &["ops".to_string()]);
+        
+        let request = next_request(&mut requests).await;
+        assert_eq!(request.body, serde_json::to_string(&message(7)).unwrap());
+        assert_eq!(request.headers["content-type"], "application/json");
+        assert_eq!(request.headers["authorization"], "Bearer token");
+        
+        let signature = request.headers["x-miniover-signature"].strip_prefix("sha256=").expect("sha256= prefix");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+        mac.update(request.body.as_bytes());
+        mac.verify_slice(&unhex(signature)).expect("signature matches the body");
+        
+        queue_on_disk(state_dir.path(), |queue| queue.entries.is_empty()).await;
+    }
+    
+    #[tokio::test]
+    async fn only_posts_to_matching_or_requested_sinks() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let (url, mut requests) = endpoint(&[200]).await;
+        let webhooks = Webhooks::start(&[sink("ops", &url)], state_dir.path()).unwrap();
+        
+        webhooks.dispatch(&message(1), &[]);
+        webhooks.dispatch(&message(2), &["other".to_string()]);
+        webhooks.dispatch(&message(3), &["ops".to_string()]);
+        
+        let request = next_request(&mut requests).await;
+        assert_eq!(serde_json::from_str::<Message>(&request.body).unwrap().id, 3);
+        queue_on_disk(state_dir.path(), |queue| queue.entries.is_empty()).await;
+        assert!(requests.try_recv().is_err());
+    }
+    
+    #[tokio::test]
+    async fn retries_server_errors_across_restarts_and_drops_rejections() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let (flaky_url, mut flaky) = endpoint(&[503, 200]).await;
+        let (strict_url, mut strict) = endpoint(&[400]).await;
+        let sinks = [sink("flaky", &flaky_url), sink("strict", &strict_url)];
+        let requested = ["flaky".to_string(), "strict".to_string()];
+        
+        let webhooks = Webhooks::start(&sinks, state_dir.path()).unwrap();
+        webhooks.dispatch(&message(1), &requested);
+        next_request(&mut flaky).await;
+        next_request(&mut strict).await;
+        
+        // The 400 is dropped, the 503 stays queued for a retry
+        let queue = queue_on_disk(state_dir.path(), |queue| {
+            queue.entries.len() == 1 && queue.entries[0].attempts == 1
+        })
+        .await;
+        assert_eq!(queue.entries[0].sink, "flaky");
+        assert_eq!(queue.entries[0].message_id, 1);
+        assert_eq!(queue.entries[0].last_error.as_deref(), Some("HTTP 503 Service Unavailable"));
+        assert!(queue.entries[0].next_attempt_at > unix_now());
+        drop(webhooks);
+        
+        // The next run picks it up from disk and retries it once due
+        let _webhooks = Webhooks::start(&sinks, state_dir.path()).unwrap();
+        let retried = next_request(&mut flaky).await;
+        assert_eq!(retried.body, serde_json::to_string(&message(1)).unwrap());
+        queue_on_disk(state_dir.path(), |queue| queue.entries.is_empty()).await;
+        assert!(strict.try_recv().is_err());
+    }
+    
+    #[tokio::test]
+    async fn a_dead_sink_does_not_delay_the_others() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let dead_url = dead_endpoint().await;
+        let (url, mut requests) = endpoint(&[200]).await;
+        let webhooks = Webhooks::start(&[sink("dead", &dead_url), sink("live", &url)], state_dir.path()).unwrap();
+        
+        for id in 1..=3 {
+            webhooks.dispatch(&message(id), &["dead".to_string(), "live".to_string()]);
+        }
+        
+        // Well within the dead sink's request timeout, and still in order
+        for id in 1..=3 {
+            let request = time::timeout(Duration::from_secs(3), requests.recv()).await.expect("held up").unwrap();
+            assert_eq!(serde_json::from_str::<Message>(&request.body).unwrap().id, id);
+        }
+    }
+}